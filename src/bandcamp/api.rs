@@ -1,8 +1,9 @@
 #![allow(non_camel_case_types, unreachable_patterns)]
-use reqwest;
 use std::collections::HashMap;
 use url::Url;
 
+use super::{client, Error, Result};
+
 pub struct Api<'a> {
     base_url: &'a str,
 }
@@ -10,6 +11,38 @@ pub struct Api<'a> {
 pub const DISCOVER_API: Api = Api {
     base_url: "https://bandcamp.com/api/discover/3",
 };
+pub const ALBUM_URL: &str = "https://{subdomain}.bandcamp.com/album/{slug}";
+
+/// Where Bandcamp is reached, replaceable to run against a local server
+#[derive(Clone, Debug)]
+pub struct Site {
+    pub discover_api: String,
+    /// Album page address, with `{subdomain}` and `{slug}` placeholders
+    pub album_url: String,
+}
+
+impl Default for Site {
+    fn default() -> Self {
+        Site {
+            discover_api: DISCOVER_API.base_url.to_string(),
+            album_url: ALBUM_URL.to_string(),
+        }
+    }
+}
+
+impl Site {
+    pub fn discover_api(&self) -> Api<'_> {
+        Api {
+            base_url: &self.discover_api,
+        }
+    }
+
+    pub fn album_url(&self, subdomain: &str, slug: &str) -> String {
+        self.album_url
+            .replace("{subdomain}", subdomain)
+            .replace("{slug}", slug)
+    }
+}
 
 #[derive(Clone, Default)]
 pub struct Function {
@@ -17,7 +50,7 @@ pub struct Function {
     parameters: HashMap<String, String>,
 }
 
-#[derive(Debug, PartialEq, Default, strum::EnumString, strum::Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, strum::EnumString, strum::Display)]
 pub enum Genre {
     #[default]
     all,
@@ -61,7 +94,7 @@ pub enum Genre {
     latin,
 }
 
-#[derive(Debug, PartialEq, Default, strum::EnumString, strum::Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, strum::EnumString, strum::Display)]
 pub enum DiscoveryType {
     #[default]
     top,
@@ -69,14 +102,14 @@ pub enum DiscoveryType {
     rec,
 }
 
-#[derive(Debug, PartialEq, Default, strum::EnumString, strum::Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, strum::EnumString, strum::Display)]
 pub enum RecommendedType {
     #[default]
     most,
     latest,
 }
 
-#[derive(Debug, PartialEq, Default, strum::EnumString, strum::Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, strum::EnumString, strum::Display)]
 pub enum Format {
     #[default]
     all,
@@ -132,14 +165,12 @@ impl Function {
 }
 
 impl Api<'_> {
-    pub fn build_query(&self, func: &Function) -> Result<Url, Box<dyn std::error::Error>> {
+    pub fn build_query(&self, func: &Function) -> Result<Url> {
         let mut url = Url::parse(self.base_url)?;
 
-        if let Ok(mut segments) = url.path_segments_mut() {
-            segments.extend([func.name.clone()]);
-        } else {
-            Err("Failed to extend onto url")?;
-        }
+        url.path_segments_mut()
+            .map_err(|_| Error::NotABaseUrl(self.base_url.to_string()))?
+            .extend([func.name.clone()]);
 
         let mut pairs = url.query_pairs_mut();
         pairs.extend_pairs(func.parameters.iter());
@@ -151,36 +182,83 @@ impl Api<'_> {
         Ok(url.to_owned())
     }
 
-    pub fn request(url: Url) -> Result<String, Box<dyn std::error::Error>> {
-        let response = reqwest::blocking::get(url)?;
-
-        if response.status().is_success() {
-            let body = response.bytes()?.to_vec();
-            let json = String::from_utf8(body)?;
-
-            if !gjson::valid(&json) {
-                return Err("Failed to get valid json".into());
-            }
-
-            Ok(json)
-        } else {
-            Err(format!(
-                "Failed to get a successful response: {}",
-                response.status()
-            ))?
+    pub fn request(url: Url) -> Result<String> {
+        let json = client::get(url)?.text()?;
+        if !gjson::valid(&json) {
+            return Err(Error::InvalidJson);
         }
+
+        Ok(json)
     }
 }
 
 #[test]
 fn test_query_request() {
-    let url = DISCOVER_API
+    use super::mock::{fixture, MockResponse, MockServer};
+
+    let server = MockServer::start([(
+        "/api/discover/3/get_web",
+        vec![MockResponse::ok(fixture("discover.json"))],
+    )]);
+    let site = server.site();
+    let url = site
+        .discover_api()
         .build_query(&Function::get_web(0, None, None, None, None))
         .expect("Failed to build url");
+    assert_eq!(url.path(), "/api/discover/3/get_web");
+    assert!(url.query_pairs().any(|(k, v)| k == "p" && v == "0"));
+
+    let json = Api::request(url).expect("Failed to request");
+    assert_eq!(gjson::get(&json, "items.#").i32(), 3);
+    assert_eq!(
+        site.album_url("halcyondrift", "night-shapes"),
+        server.url("/halcyondrift/album/night-shapes")
+    );
+}
 
-    let mut _res: Result<String, Box<dyn std::error::Error>> = Ok(String::default());
-    _res = Api::request(url);
+#[test]
+fn test_build_query_errors() {
+    let function = Function::get_web(0, None, None, None, None);
+    assert!(matches!(
+        Api {
+            base_url: "https//"
+        }
+        .build_query(&function),
+        Err(Error::InvalidUrl(_))
+    ));
+    assert!(matches!(
+        Api {
+            base_url: "mailto:api@bandcamp.com"
+        }
+        .build_query(&function),
+        Err(Error::NotABaseUrl(url)) if url == "mailto:api@bandcamp.com"
+    ));
+}
+
+#[test]
+fn test_request_errors() {
+    use super::mock::{MockResponse, MockServer};
 
-    assert!(_res.is_ok());
-    assert!(_res.unwrap() != String::default());
+    let server = MockServer::start([
+        (
+            "/busy",
+            vec![
+                MockResponse::status(429).header("Retry-After", "0"),
+                MockResponse::status(503),
+                MockResponse::ok("{}".to_string()),
+            ],
+        ),
+        ("/html", vec![MockResponse::ok("<html>".to_string())]),
+        ("/gone", vec![MockResponse::status(410)]),
+    ]);
+    let url = |path| Url::parse(&server.url(path)).unwrap();
+    assert_eq!(Api::request(url("/busy")).unwrap(), "{}");
+    assert!(matches!(
+        Api::request(url("/html")),
+        Err(Error::InvalidJson)
+    ));
+    assert!(matches!(
+        Api::request(url("/gone")),
+        Err(Error::HttpStatus(410))
+    ));
 }
@@ -0,0 +1,313 @@
+//! The one blocking HTTP client every request to Bandcamp goes through, sharing a connection
+//! pool, timeouts, retries and a rate limit. Audio downloads go through a second one without
+//! the overall timeout, and audio streams use an async client built from the same network
+//! settings.
+
+use std::{
+    fs,
+    net::IpAddr,
+    path::PathBuf,
+    sync::Mutex,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use chrono::DateTime;
+use once_cell::sync::{Lazy, OnceCell};
+use reqwest::{
+    blocking::{Client, Response},
+    header::{HeaderMap, RETRY_AFTER},
+    Certificate, IntoUrl, Proxy, StatusCode,
+};
+use tracing::{debug, warn};
+
+use super::{Error, Result};
+
+pub const USER_AGENT: &str = concat!("bandmix/", env!("CARGO_PKG_VERSION"));
+/// Requests allowed back to back before the rate limit applies
+const BURST: f64 = 4.0;
+/// Longest `Retry-After` that is honored, anything longer counts as a failure
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// How connections are made, shared by every client
+#[derive(Clone, Debug, Default)]
+pub struct NetworkSettings {
+    /// `http://`, `https://` or `socks5://` proxy for every request, in place of the
+    /// `HTTP_PROXY` style environment variables
+    pub proxy: Option<String>,
+    /// PEM file of extra root certificates to trust, such as a corporate proxy's
+    pub ca_bundle: Option<PathBuf>,
+    /// Local address, or network interface name, to connect from
+    pub bind: Option<String>,
+}
+
+impl NetworkSettings {
+    fn proxy(&self) -> Result<Option<Proxy>> {
+        self.proxy
+            .as_deref()
+            .map(|url| Proxy::all(url).map_err(|e| Error::Config(format!("proxy {}: {}", url, e))))
+            .transpose()
+    }
+
+    fn certificates(&self) -> Result<Vec<Certificate>> {
+        let Some(path) = &self.ca_bundle else {
+            return Ok(Vec::new());
+        };
+        let config_error = |e: &dyn std::fmt::Display| {
+            Error::Config(format!("CA bundle {}: {}", path.display(), e))
+        };
+        let pem = fs::read(path).map_err(|e| config_error(&e))?;
+        Certificate::from_pem_bundle(&pem).map_err(|e| config_error(&e))
+    }
+}
+
+/// Applies [`NetworkSettings`] to either kind of client builder, which share method names
+/// but no trait.
+macro_rules! apply_network {
+    ($builder:expr, $network:expr) => {{
+        let network: &NetworkSettings = $network;
+        let mut builder = $builder;
+        match network.proxy() {
+            Ok(Some(proxy)) => builder = builder.proxy(proxy),
+            Ok(None) => {}
+            Err(error) => warn!("{}", error),
+        }
+        match network.certificates() {
+            Ok(certificates) => {
+                for certificate in certificates {
+                    builder = builder.add_root_certificate(certificate);
+                }
+            }
+            Err(error) => warn!("{}", error),
+        }
+        if let Some(bind) = network.bind.as_deref() {
+            match bind.parse::<IpAddr>() {
+                Ok(address) => builder = builder.local_address(address),
+                #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+                Err(_) => builder = builder.interface(bind),
+                #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+                Err(_) => warn!("Binding to interface {} by name is not supported", bind),
+            }
+        }
+        builder
+    }};
+}
+
+#[derive(Clone, Debug)]
+pub struct ClientSettings {
+    /// Limit on a whole request, body included
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// Attempts after the first for server errors, 429s and network failures
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after
+    pub backoff: Duration,
+    /// Requests per second sustained across every thread, 0 disables the limit
+    pub rate_limit: f64,
+    pub network: NetworkSettings,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            rate_limit: 1.0,
+            network: NetworkSettings::default(),
+        }
+    }
+}
+
+static SETTINGS: OnceCell<ClientSettings> = OnceCell::new();
+
+static CLIENT: Lazy<Client> = Lazy::new(|| {
+    let settings = settings();
+    let builder = Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(settings.timeout)
+        .connect_timeout(settings.connect_timeout);
+    apply_network!(builder, &settings.network)
+        .build()
+        .unwrap_or_else(|error| {
+            warn!("Failed to build HTTP client, using defaults : {}", error);
+            Client::new()
+        })
+});
+
+/// No overall timeout, as a whole track can take longer than that to download on a slow link
+static DOWNLOAD_CLIENT: Lazy<Client> = Lazy::new(|| {
+    let settings = settings();
+    let builder = Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(None)
+        .connect_timeout(settings.connect_timeout);
+    apply_network!(builder, &settings.network)
+        .build()
+        .unwrap_or_else(|error| {
+            warn!(
+                "Failed to build download client, using defaults : {}",
+                error
+            );
+            Client::new()
+        })
+});
+
+/// No overall timeout, as a stream takes as long as the track does to play
+static STREAM_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    let settings = settings();
+    let builder = reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(settings.connect_timeout);
+    apply_network!(builder, &settings.network)
+        .build()
+        .unwrap_or_else(|error| {
+            warn!("Failed to build stream client, using defaults : {}", error);
+            reqwest::Client::new()
+        })
+});
+
+static BUCKET: Lazy<Mutex<TokenBucket>> =
+    Lazy::new(|| Mutex::new(TokenBucket::new(settings().rate_limit, BURST)));
+
+/// Set up the clients, before any request is made. Later calls are ignored.
+pub fn configure(settings: ClientSettings) -> Result<()> {
+    settings.network.proxy()?;
+    settings.network.certificates()?;
+    if SETTINGS.set(settings).is_err() {
+        warn!("HTTP client already configured");
+    }
+    Ok(())
+}
+
+/// Async client for audio streams, sharing the connection settings of [`get`]
+pub fn stream_client() -> reqwest::Client {
+    STREAM_CLIENT.clone()
+}
+
+fn settings() -> &'static ClientSettings {
+    SETTINGS.get_or_init(ClientSettings::default)
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64) -> TokenBucket {
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take a token, returning how long to wait before using it
+    fn take(&mut self, now: Instant) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity) - 1.0;
+        self.last = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+fn throttle() {
+    let wait = match BUCKET.lock() {
+        Ok(mut bucket) => bucket.take(Instant::now()),
+        Err(_) => Duration::ZERO,
+    };
+    if !wait.is_zero() {
+        debug!("Rate limited for {:?}", wait);
+        sleep(wait);
+    }
+}
+
+/// Delay asked for by a `Retry-After` header, in seconds or as an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.to_utc() - chrono::Utc::now()).to_std().ok()
+}
+
+fn retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// GET `url`, retrying transient failures, and fail on any unsuccessful status.
+pub fn get(url: impl IntoUrl) -> Result<Response> {
+    send(&CLIENT, url)
+}
+
+/// [`get`] for large bodies such as audio, which only time out while connecting.
+pub fn download(url: impl IntoUrl) -> Result<Response> {
+    send(&DOWNLOAD_CLIENT, url)
+}
+
+fn send(client: &Client, url: impl IntoUrl) -> Result<Response> {
+    let url = url.into_url()?;
+    let settings = settings();
+    let mut attempt = 0;
+    loop {
+        throttle();
+        let backoff = settings.backoff * 2u32.saturating_pow(attempt);
+        let error = match client.get(url.clone()).send() {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                if !retryable(status) || attempt >= settings.retries {
+                    return Err(Error::HttpStatus(status.as_u16()));
+                }
+                match retry_after(response.headers()) {
+                    Some(wait) if wait > MAX_RETRY_AFTER => {
+                        return Err(Error::HttpStatus(status.as_u16()))
+                    }
+                    Some(wait) => {
+                        debug!("{} for {}, retrying after {:?}", status, url, wait);
+                        sleep(wait);
+                        attempt += 1;
+                        continue;
+                    }
+                    None => Error::HttpStatus(status.as_u16()),
+                }
+            }
+            Err(error) if attempt < settings.retries && !error.is_builder() => error.into(),
+            Err(error) => return Err(error.into()),
+        };
+        debug!("{} for {}, retrying in {:?}", error, url, backoff);
+        sleep(backoff);
+        attempt += 1;
+    }
+}
+
+#[test]
+fn test_token_bucket() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(2.0, 2.0);
+    assert_eq!(bucket.take(start), Duration::ZERO);
+    assert_eq!(bucket.take(start), Duration::ZERO);
+    assert_eq!(bucket.take(start), Duration::from_millis(500));
+    // The late request's token is spent by the time it is refilled
+    assert_eq!(
+        bucket.take(start + Duration::from_millis(500)),
+        Duration::from_millis(500)
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert(RETRY_AFTER, "7".parse().unwrap());
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+}
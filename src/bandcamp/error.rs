@@ -0,0 +1,24 @@
+use thiserror::Error;
+
+/// Failures talking to Bandcamp, kept apart so a layout change can be told from an outage
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("network error: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error("HTTP status {0}")]
+    HttpStatus(u16),
+    #[error("invalid url: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("cannot add a path to {0}, which is not a base url")]
+    NotABaseUrl(String),
+    #[error("invalid JSON response")]
+    InvalidJson,
+    #[error("missing {0} in page, the layout may have changed")]
+    ScrapeMissingField(&'static str),
+    #[error("no stream url for {0}")]
+    NoStreamUrl(String),
+    #[error("invalid network settings: {0}")]
+    Config(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,140 @@
+//! A local stand-in for Bandcamp that serves recorded fixtures from `tests/fixtures`, so tests
+//! run offline.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use super::{
+    api::Site,
+    client::{self, ClientSettings},
+};
+
+/// Contents of a file in `tests/fixtures`
+pub fn fixture(name: &str) -> String {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name]
+        .iter()
+        .collect();
+    fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+#[derive(Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl MockResponse {
+    pub fn ok(body: String) -> MockResponse {
+        MockResponse {
+            status: 200,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    pub fn status(status: u16) -> MockResponse {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: &str) -> MockResponse {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+}
+
+type Routes = HashMap<String, Vec<MockResponse>>;
+
+pub struct MockServer {
+    address: SocketAddr,
+}
+
+impl MockServer {
+    /// Serve each path's responses in turn, repeating the last one. Other paths get a 404.
+    pub fn start<'a>(routes: impl IntoIterator<Item = (&'a str, Vec<MockResponse>)>) -> Self {
+        // Nothing to be polite to, so tests need not wait on the rate limit or backoff
+        let _ = client::configure(ClientSettings {
+            rate_limit: 0.0,
+            backoff: Duration::from_millis(10),
+            ..Default::default()
+        });
+        let routes: Routes = routes
+            .into_iter()
+            .map(|(path, responses)| (path.to_string(), responses))
+            .collect();
+        let routes = Arc::new(Mutex::new(routes));
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+        let address = listener.local_addr().expect("Mock server has no address");
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let routes = Arc::clone(&routes);
+                thread::spawn(move || respond(stream, &routes));
+            }
+        });
+        MockServer { address }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    /// Bandcamp's endpoints, all on this server
+    pub fn site(&self) -> Site {
+        Site {
+            discover_api: self.url("/api/discover/3"),
+            album_url: self.url("/{subdomain}/album/{slug}"),
+        }
+    }
+}
+
+fn respond(stream: TcpStream, routes: &Mutex<Routes>) {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    let mut line = String::new();
+    while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+        line.clear();
+    }
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    let response = routes
+        .lock()
+        .ok()
+        .and_then(|mut routes| {
+            let responses = routes.get_mut(path)?;
+            match responses.len() {
+                0 => None,
+                1 => responses.first().cloned(),
+                _ => Some(responses.remove(0)),
+            }
+        })
+        .unwrap_or_else(|| MockResponse::status(404));
+
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head += &format!("{}: {}\r\n", name, value);
+    }
+    head += "\r\n";
+    let mut stream = &stream;
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(response.body.as_bytes());
+}
@@ -1,3 +1,9 @@
 pub mod api;
+pub mod client;
+pub mod error;
+#[cfg(test)]
+pub mod mock;
 pub mod models;
 pub mod spider;
+
+pub use error::{Error, Result};
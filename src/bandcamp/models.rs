@@ -2,17 +2,29 @@
 
 use std::collections::BTreeMap;
 
+use serde::Serialize;
+
 pub type AlbumID = u32;
 pub type TrackID = u32;
 pub type TrackNum = i32;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Track {
     pub id: TrackID,
     pub num: TrackNum,
     pub name: String,
     pub url: String,
-    // pub lyrics: Option<String>,
+    /// Seconds
+    pub duration: Option<f64>,
+    /// When credited separately from the album, as on compilations
+    pub artist: Option<String>,
+    pub lyrics: Option<String>,
+    /// Lyrics exist, though they may not have been on the page
+    pub has_lyrics: bool,
+    pub streaming: bool,
+    pub downloadable: bool,
+    pub page_url: Option<String>,
+    pub art_url: Option<String>,
     pub album_id: AlbumID,
 }
 
@@ -28,7 +40,48 @@ impl std::fmt::Display for Track {
     }
 }
 
-#[derive(Default, Debug)]
+/// A way of scraping an album page, recorded so a change in Bandcamp's layout shows up as a
+/// fallback being used
+#[derive(Debug, Clone, Copy, PartialEq, strum::Display)]
+pub enum Strategy {
+    #[strum(to_string = "ld+json")]
+    LdJson,
+    #[strum(to_string = "data-tralbum")]
+    DataTralbum,
+    /// An item of the discover feed, without the album page
+    #[strum(to_string = "discover API")]
+    DiscoverApi,
+}
+
+/// A format an album is sold in, digital or physical
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Release {
+    pub name: String,
+    /// Such as "Digital", "Vinyl", "Cassette" or "CD"
+    pub format: String,
+    pub price: Option<f64>,
+    pub currency: Option<String>,
+}
+
+impl std::fmt::Display for Release {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.format)?;
+        if let Some(price) = self.price {
+            write!(f, " {}", format_price(price, self.currency.as_deref()))?;
+        }
+        Ok(())
+    }
+}
+
+/// A price to two places with its currency code, such as `7.00 GBP`
+pub fn format_price(price: f64, currency: Option<&str>) -> String {
+    match currency {
+        Some(currency) => format!("{:.2} {}", price, currency),
+        None => format!("{:.2}", price),
+    }
+}
+
+#[derive(Default, Debug, Clone)]
 pub struct Album {
     pub id: AlbumID,
     pub artist: String,
@@ -40,6 +93,28 @@ pub struct Album {
     pub tags: Option<String>,
     pub album_art_url: Option<String>,
     pub artist_art_url: Option<String>,
+    /// Publishing label, `None` when self-released
+    pub label: Option<String>,
+    /// Of the digital album, the minimum when the listener names their price
+    pub price: Option<f64>,
+    pub currency: Option<String>,
+    pub name_your_price: bool,
+    pub about: Option<String>,
+    pub credits: Option<String>,
+    /// Where the artist is based
+    pub location: Option<String>,
+    pub releases: Vec<Release>,
+    /// Scrapers that found the album, first the one its data was mostly taken from
+    pub strategies: Vec<Strategy>,
+}
+
+impl Album {
+    /// Scraped from its page, rather than only known from the discover feed
+    pub fn complete(&self) -> bool {
+        self.strategies
+            .iter()
+            .any(|strategy| *strategy != Strategy::DiscoverApi)
+    }
 }
 
 impl std::fmt::Display for Album {
@@ -49,6 +124,16 @@ impl std::fmt::Display for Album {
             result += &format!("\n{}", self.url);
         }
         result += &format!("\n {}", self.artist);
+        if let Some(label) = &self.label {
+            result += &format!("\n Label {}", label);
+        }
+        for release in &self.releases {
+            result += &format!("\n {}", release);
+        }
+        if !self.strategies.is_empty() {
+            let strategies: Vec<String> = self.strategies.iter().map(|s| s.to_string()).collect();
+            result += &format!("\n Scraped by {}", strategies.join(", "));
+        }
         for track in self.tracks.values() {
             let mut extra = " ";
             if let Some(featured) = self.featured_track_num {
@@ -2,17 +2,61 @@
 
 use std::collections::BTreeMap;
 
-use anyhow::Result;
 use html_escape::decode_html_entities;
 use scraper::{Html, Selector};
-use tracing::{trace, warn};
+use tracing::{debug, trace, warn};
+use url::Url;
+
+use super::{
+    client,
+    models::{Album, AlbumID, Release, Strategy, Track, TrackID, TrackNum},
+    Error, Result,
+};
+
+/// The string at `path`, unless empty or missing
+fn text(value: &gjson::Value, path: &str) -> Option<String> {
+    let text = decode_html_entities(value.get(path).str().trim()).to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// Seconds in an ISO 8601 duration such as `P00H03M25S`
+fn parse_duration(duration: &str) -> Option<f64> {
+    let time = duration.strip_prefix('P')?.trim_start_matches('T');
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    for c in time.chars() {
+        let unit = match c {
+            'H' => 3600.0,
+            'M' => 60.0,
+            'S' => 1.0,
+            'T' => continue,
+            c => {
+                number.push(c);
+                continue;
+            }
+        };
+        seconds += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    (seconds > 0.0).then_some(seconds)
+}
 
-use super::models::{Album, Track};
+/// Readable name of a schema.org `musicReleaseFormat`, such as `VinylFormat`
+fn release_format(format: &str) -> String {
+    match format.trim_end_matches("Format") {
+        "" => "Other".to_string(),
+        format => format.to_string(),
+    }
+}
 
 /// Parse data from the node: `document.querySelector('script[data-tralbum]')`
-fn scrape_by_data_tralbum(dom: &Html) -> Option<Album> {
-    let selector = Selector::parse("script[data-tralbum]").ok()?;
-    let element = dom.select(&selector).next()?;
+fn scrape_by_data_tralbum(dom: &Html) -> Result<Album> {
+    let selector = Selector::parse("script[data-tralbum]")
+        .map_err(|_| Error::ScrapeMissingField("data-tralbum"))?;
+    let element = dom
+        .select(&selector)
+        .next()
+        .ok_or(Error::ScrapeMissingField("data-tralbum"))?;
 
     let mut album = Album::default();
 
@@ -33,9 +77,37 @@ fn scrape_by_data_tralbum(dom: &Html) -> Option<Album> {
             if album.name.is_empty() {
                 album.name = data.get("current.title").to_string();
             }
+            if album.artist.is_empty() {
+                album.artist = data.get("artist").to_string();
+            }
+            if album.url.is_empty() {
+                album.url = data.get("url").to_string();
+            }
+            let base_url = Url::parse(&album.url).ok();
             let mut count = 0;
 
             album.release_date = data.get("album_release_date").to_string();
+            album.about = text(&data, "current.about");
+            album.credits = text(&data, "current.credits");
+            // A free download is priced at whatever the listener names, from nothing
+            if text(&data, "freeDownloadPage").is_some() {
+                album.name_your_price = true;
+                album.price = Some(0.0);
+            }
+            album.releases = data
+                .get("packages")
+                .array()
+                .iter()
+                .map(|package| Release {
+                    name: text(package, "title").unwrap_or_default(),
+                    format: text(package, "type_name").unwrap_or_else(|| "Other".to_string()),
+                    price: package
+                        .get("price")
+                        .exists()
+                        .then(|| package.get("price").f64()),
+                    currency: text(package, "currency"),
+                })
+                .collect();
             album.tracks = data
                 .get("trackinfo")
                 .array()
@@ -53,9 +125,19 @@ fn scrape_by_data_tralbum(dom: &Html) -> Option<Album> {
                         Track {
                             id,
                             num: (index + 1) as i32,
-                            name: item.get("title").to_string(),
+                            name: item.get("title").to_string().replace('/', ":"),
                             url: item.get("file.mp3-128").to_string(),
-                            // lyrics: None,
+                            duration: Some(item.get("duration").f64()).filter(|d| *d > 0.0),
+                            artist: text(item, "artist"),
+                            lyrics: text(item, "lyrics"),
+                            has_lyrics: item.get("has_lyrics").bool(),
+                            streaming: item.get("streaming").bool(),
+                            downloadable: item.get("is_downloadable").bool(),
+                            page_url: text(item, "title_link").and_then(|link| {
+                                Some(base_url.as_ref()?.join(&link).ok()?.to_string())
+                            }),
+                            art_url: text(item, "art_id")
+                                .map(|id| format!("https://f4.bcbits.com/img/a{}_10.jpg", id)),
                             album_id: album.id,
                         },
                     )
@@ -64,28 +146,55 @@ fn scrape_by_data_tralbum(dom: &Html) -> Option<Album> {
         }
     }
 
-    if album.id == 0 {
-        warn!("Album {} has an id of 0", &album.name);
-    }
+    album.strategies = vec![Strategy::DataTralbum];
 
-    Some(album)
+    Ok(album)
 }
 
-fn find_track_album_by_name(dom: &Html, track_name: &gjson::Value) -> Option<Album> {
-    trace!("Searching for track by name {}", track_name);
-    scrape_by_data_tralbum(dom)
+/// A `MusicRecording` from ld+json, either a track page itself or an item in an album's list
+fn ld_json_track(recording: &gjson::Value, num: TrackNum, album_id: AlbumID) -> Result<Track> {
+    const FILE_PATH: &str = "additionalProperty.#(name=file_mp3-128).value";
+    const TRACK_ID: &str = "additionalProperty.#(name=track_id).value";
+
+    let id = recording
+        .get(TRACK_ID)
+        .to_string()
+        .parse()
+        .map_err(|_| Error::ScrapeMissingField("track_id"))?;
+    let url = text(recording, FILE_PATH).unwrap_or_default();
+    let lyrics = text(recording, "recordingOf.lyrics.text");
+    Ok(Track {
+        id,
+        num,
+        // Names can be escaped twice
+        name: decode_html_entities(&text(recording, "name").unwrap_or_default()).replace('/', ":"),
+        streaming: !url.is_empty(),
+        url,
+        duration: parse_duration(recording.get("duration").str()),
+        artist: text(recording, "byArtist.name"),
+        has_lyrics: lyrics.is_some(),
+        lyrics,
+        downloadable: false,
+        page_url: text(recording, "@id"),
+        art_url: text(recording, "image"),
+        album_id,
+    })
 }
 
 /// Parse data from the node: `document.querySelector('script[type="application/ld+json"]')`
-fn scrape_by_application_ld_json(dom: &Html) -> Option<Album> {
-    let selector = Selector::parse("script[type='application/ld+json']").unwrap();
-    let element = dom.select(&selector).next().unwrap();
+fn scrape_by_application_ld_json(dom: &Html) -> Result<Album> {
+    let selector = Selector::parse("script[type='application/ld+json']")
+        .map_err(|_| Error::ScrapeMissingField("ld+json"))?;
+    let element = dom
+        .select(&selector)
+        .next()
+        .ok_or(Error::ScrapeMissingField("ld+json"))?;
 
     let json = element.inner_html();
     let json = json.as_str();
 
     if !gjson::valid(json) {
-        return None;
+        return Err(Error::InvalidJson);
     }
 
     let mut album = Album::default();
@@ -100,7 +209,7 @@ fn scrape_by_application_ld_json(dom: &Html) -> Option<Album> {
         .get("albumRelease.#(additionalProperty.#(value=a)).additionalProperty.#(name=item_id).value")
         .to_string()
         .parse::<u32>()
-        .ok()?;
+        .map_err(|_| Error::ScrapeMissingField("item_id"))?;
     album.featured_track_num = item
         .get("additionalProperty.#(name=featured_track_num).value")
         .to_string()
@@ -128,142 +237,313 @@ fn scrape_by_application_ld_json(dom: &Html) -> Option<Album> {
     album.album_art_url = Some(item.get("image").to_string());
     album.artist = item.get("byArtist.name").to_string();
     album.artist_art_url = Some(item.get("byArtist.image").to_string());
+    // The publisher is the artist themselves unless a label put the album out
+    album.label = text(&item, "publisher.name")
+        .filter(|label| decode_html_entities(&album.artist) != label.as_str());
+    album.about = text(&item, "description");
+    album.credits = text(&item, "creditText");
+    album.location = text(&item, "byArtist.foundingLocation.name")
+        .or_else(|| text(&item, "publisher.foundingLocation.name"));
+    for release in item.get("albumRelease").array() {
+        let Some(format) = text(&release, "musicReleaseFormat") else {
+            continue;
+        };
+        let offer = release.get("offers");
+        let price = offer
+            .get("price")
+            .exists()
+            .then(|| offer.get("price").f64());
+        let currency = text(&offer, "priceCurrency");
+        if format == "DigitalFormat" {
+            // Only given when the listener can pay more than the price
+            let minimum = offer.get("priceSpecification.minPrice");
+            album.name_your_price = minimum.exists() || price == Some(0.0);
+            album.price = if minimum.exists() {
+                Some(minimum.f64())
+            } else {
+                price
+            };
+            album.currency = currency.clone();
+        }
+        album.releases.push(Release {
+            name: text(&release, "name").unwrap_or_default(),
+            format: release_format(&format),
+            price,
+            currency,
+        });
+    }
 
     let tracks = item.get("track.itemListElement");
 
-    const FILE_PATH: &str = "additionalProperty.#(name=file_mp3-128).value";
-    const TRACK_ID: &str = "additionalProperty.#(name=track_id).value";
-
-    let mut tralbum_album = None;
-
-    // case when current url is just a track
+    // Tracks without a stream url are kept so other strategies can fill it in
     album.tracks = if tracks.array().is_empty() {
-        let mut url = decode_html_entities(&item.get(FILE_PATH).to_string()).to_string();
-        let track_id = item.get(TRACK_ID).to_string().parse().ok()?;
-        let track_name = item.get("name");
-
-        if url.is_empty() {
-            tralbum_album = tralbum_album.or(find_track_album_by_name(dom, &track_name));
-            if let Some(tralbum_album) = &tralbum_album {
-                let track_url = tralbum_album.tracks.iter().find_map(|(_id, inner_track)| {
-                    if inner_track.name == track_name.str() {
-                        Some(inner_track.url.clone())
-                    } else {
-                        None
-                    }
-                });
-                if let Some(track_url) = track_url {
-                    url = track_url;
-                } else {
-                    // no url is found for the track's file
-                    warn!("No downloadable url found for '{}', skipping.", &track_name);
-                    return None;
-                }
-            } else {
-                warn!("No downloadable url found for '{}', skipping.", &track_name);
-                return None;
-            }
-        }
-
-        BTreeMap::from([(
-            track_id,
-            Track {
-                id: track_id,
-                num: 1,
-                name: track_name.to_string().replace('/', ":"),
-                url,
-                // lyrics: None,
-                album_id: album.id,
-            },
-        )])
+        // case when current url is just a track
+        let track = ld_json_track(&item, 1, album.id)?;
+        BTreeMap::from([(track.id, track)])
     } else {
         // case when current url is an album
+        tracks
+            .array()
+            .iter()
+            .map(|track| {
+                let track =
+                    ld_json_track(&track.get("item"), track.get("position").i32(), album.id)?;
+                Ok((track.id, track))
+            })
+            .collect::<Result<_>>()?
+    };
 
-        let mut retval = BTreeMap::<u32, Track>::new();
+    album.name = String::from(html_escape::decode_html_entities(&album.name));
+    album.artist = String::from(html_escape::decode_html_entities(&album.artist));
+    album.strategies = vec![Strategy::LdJson];
 
-        for track in tracks.array() {
-            let mut url = track.get(&("item.".to_owned() + FILE_PATH)).to_string();
-            let track_id = track
-                .get(&("item.".to_owned() + TRACK_ID))
-                .to_string()
-                .parse()
-                .ok()?;
-
-            if url.is_empty() {
-                let track_name = track.get("item.name");
-                tralbum_album = tralbum_album.or(find_track_album_by_name(dom, &track_name));
-                if let Some(tralbum_album) = &tralbum_album {
-                    let track_url = tralbum_album.tracks.iter().find_map(|(_id, inner_track)| {
-                        if inner_track.name == track_name.str() {
-                            Some(inner_track.url.clone())
-                        } else {
-                            None
-                        }
-                    });
-                    if let Some(track_url) = track_url {
-                        url = track_url;
-                    } else {
-                        // no url is found for the track's file
-                        warn!("No downloadable url found for '{}', skipping.", &track_name);
-                        continue;
-                    }
-                } else {
-                    warn!("No downloadable url found for '{}', skipping.", &track_name);
-                    continue;
-                }
-            }
+    Ok(album)
+}
 
-            let name = decode_html_entities(&track.get("item.name").to_string()).replace('/', ":");
-            let name = String::from(html_escape::decode_html_entities(&name));
-
-            retval.insert(
-                track_id,
-                Track {
-                    id: track_id,
-                    num: track.get("position").i32(),
-                    name,
-                    url: decode_html_entities(&url).to_string(),
-                    // lyrics: Some(track.get("item.recordingOf.lyrics.text").to_string()),
-                    album_id: album.id,
-                },
-            );
-        }
-        retval
+/// The part of an album an item of the discover feed gives: its names, art, genre and
+/// featured track, so it can play without its page at `url` being fetched.
+pub fn discover_album(item: &gjson::Value, url: String) -> Result<Album> {
+    let id = item
+        .get("id")
+        .u64()
+        .try_into()
+        .ok()
+        .filter(|id| *id != 0)
+        .ok_or(Error::ScrapeMissingField("id"))?;
+    let mut album = Album {
+        id,
+        name: text(item, "primary_text").unwrap_or_default(),
+        artist: text(item, "secondary_text").unwrap_or_default(),
+        url,
+        release_date: text(item, "publish_date").unwrap_or_default(),
+        tags: text(item, "genre_text"),
+        album_art_url: text(item, "art_id")
+            .map(|id| format!("https://f4.bcbits.com/img/a{}_10.jpg", id)),
+        artist_art_url: text(item, "bio_image.image_id")
+            .map(|id| format!("https://f4.bcbits.com/img/{}_10.jpg", id)),
+        strategies: vec![Strategy::DiscoverApi],
+        ..Default::default()
     };
 
-    album.name = String::from(html_escape::decode_html_entities(&album.name));
-    album.artist = String::from(html_escape::decode_html_entities(&album.artist));
+    let featured = item.get("featured_track");
+    let track_id: TrackID = featured.get("id").u64().try_into().unwrap_or(0);
+    if track_id != 0 {
+        let num = featured.get("track_number").i32();
+        album.featured_track_num = (num > 0).then_some(num);
+        let url = text(&featured, "file.mp3-128").unwrap_or_default();
+        let track = Track {
+            id: track_id,
+            num: num.max(1),
+            name: text(&featured, "title")
+                .unwrap_or_default()
+                .replace('/', ":"),
+            streaming: !url.is_empty(),
+            url,
+            duration: Some(featured.get("duration").f64()).filter(|d| *d > 0.0),
+            art_url: album.album_art_url.clone(),
+            album_id: id,
+            ..Default::default()
+        };
+        album.tracks.insert(track.id, track);
+    }
+    Ok(album)
+}
+
+/// Fill in whatever `album` is missing from `other`, found for the same album another way.
+pub fn merge(album: &mut Album, other: Album) {
+    fn fill(field: &mut String, other: String) {
+        if field.is_empty() {
+            *field = other;
+        }
+    }
 
-    Some(album)
+    if album.id == 0 {
+        album.id = other.id;
+    }
+    fill(&mut album.name, other.name);
+    fill(&mut album.artist, other.artist);
+    fill(&mut album.url, other.url);
+    fill(&mut album.release_date, other.release_date);
+    album.featured_track_num = album.featured_track_num.or(other.featured_track_num);
+    album.tags = album.tags.take().or(other.tags);
+    album.album_art_url = album.album_art_url.take().or(other.album_art_url);
+    album.artist_art_url = album.artist_art_url.take().or(other.artist_art_url);
+    album.label = album.label.take().or(other.label);
+    if album.price.is_none() {
+        album.price = other.price;
+        album.currency = other.currency;
+    }
+    album.name_your_price |= other.name_your_price;
+    album.about = album.about.take().or(other.about);
+    album.credits = album.credits.take().or(other.credits);
+    album.location = album.location.take().or(other.location);
+    if album.releases.is_empty() {
+        album.releases = other.releases;
+    }
+    for (id, track) in other.tracks {
+        match album.tracks.get_mut(&id) {
+            Some(existing) => {
+                fill(&mut existing.name, track.name);
+                fill(&mut existing.url, track.url);
+                existing.duration = existing.duration.or(track.duration);
+                existing.artist = existing.artist.take().or(track.artist);
+                existing.lyrics = existing.lyrics.take().or(track.lyrics);
+                existing.has_lyrics |= track.has_lyrics;
+                existing.streaming |= track.streaming;
+                existing.downloadable |= track.downloadable;
+                existing.page_url = existing.page_url.take().or(track.page_url);
+                existing.art_url = existing.art_url.take().or(track.art_url);
+            }
+            None => {
+                album.tracks.insert(id, track);
+            }
+        }
+    }
+    for track in album.tracks.values_mut() {
+        track.album_id = album.id;
+    }
+    album.strategies.extend(other.strategies);
 }
 
 /// Facade for `scrape_by_*` methods.
-/// Calls `scrape_by_application_ld_json` or `scrape_by_data_tralbum` internal methods if first fails.
-fn get_album(dom: &Html) -> Option<Album> {
-    scrape_by_application_ld_json(dom)
+/// Scrapes with every method, using `scrape_by_application_ld_json` first and filling in what it
+/// leaves out, or fails on, from `scrape_by_data_tralbum`.
+fn get_album(dom: &Html) -> Result<Album> {
+    let album = match (
+        scrape_by_application_ld_json(dom),
+        scrape_by_data_tralbum(dom),
+    ) {
+        (Ok(mut album), Ok(tralbum)) => {
+            merge(&mut album, tralbum);
+            album
+        }
+        (Ok(album), Err(error)) => {
+            debug!("No data-tralbum for {} : {}", album.name, error);
+            album
+        }
+        (Err(error), Ok(album)) => {
+            warn!("Scraping {} by ld+json failed : {}", album.name, error);
+            album
+        }
+        (Err(error), Err(_)) => return Err(error),
+    };
+    trace!("Scraped {} using {:?}", album.name, album.strategies);
+
+    if album.id == 0 {
+        warn!("Album {} has an id of 0", &album.name);
+    }
+    if !album.tracks.values().any(Track::valid) {
+        return Err(Error::NoStreamUrl(album.name));
+    }
+    Ok(album)
 }
 
 /// Get [`Html`] of a page.
 fn fetch_html(url: &str) -> Result<Html> {
-    let body = reqwest::blocking::get(url)?.bytes()?.to_vec();
-    let body = String::from_utf8(body)?;
+    let body = client::get(url)?.text()?;
 
     Ok(Html::parse_document(body.as_ref()))
 }
 
-pub fn fetch_album(url: &str) -> Option<Album> {
-    let html = fetch_html(url).ok()?;
-    let album = get_album(&html)?;
-    Some(album)
+pub fn fetch_album(url: &str) -> Result<Album> {
+    let html = fetch_html(url)?;
+    get_album(&html)
 }
 
 #[test]
 fn test_get_album() {
-    let mut _result = false;
-    let html = fetch_html("https://loscampesinos.bandcamp.com/album/all-hell")
-        .expect("Failed to get html");
-    let _album = get_album(&html).expect("Failed to get Album");
-    println!("{}", _album);
-    _result = true;
-    assert!(_result, "Failed to fetch album")
+    use super::mock::{fixture, MockResponse, MockServer};
+
+    let album =
+        get_album(&Html::parse_document(&fixture("album.html"))).expect("Failed to get Album");
+    assert_eq!(album.id, 2371045602);
+    assert_eq!(album.name, "Night Shapes");
+    assert_eq!(album.artist, "Halcyon Drift");
+    assert_eq!(album.featured_track_num, Some(2));
+    assert_eq!(album.tags.as_deref(), Some("electronic, ambient, Leeds"));
+    let names: Vec<&str> = album.tracks.values().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, ["Glass & Static", "Low Tide", "Sold Separately"]);
+    assert_eq!(
+        album.tracks[&1001].url,
+        "https://t4.bcbits.com/stream/aaa/mp3-128/1001?p=0&ts=1&t=x"
+    );
+    // Missing from ld+json, found through data-tralbum
+    assert_eq!(
+        album.tracks[&1002].url,
+        "https://t4.bcbits.com/stream/bbb/mp3-128/1002"
+    );
+    assert!(!album.tracks[&1003].valid());
+    assert_eq!(album.strategies, [Strategy::LdJson, Strategy::DataTralbum]);
+    let track = &album.tracks[&1001];
+    assert_eq!(track.duration, Some(205.0));
+    assert_eq!(
+        track.lyrics.as_deref(),
+        Some("Static on the glass\nLight through the blinds")
+    );
+    assert!(track.has_lyrics && track.streaming && track.downloadable);
+    assert_eq!(track.artist, None);
+    let track = &album.tracks[&1002];
+    // Whole seconds from ld+json win over data-tralbum's
+    assert_eq!(track.duration, Some(251.0));
+    assert_eq!(track.artist.as_deref(), Some("Guest Star"));
+    assert_eq!(
+        track.page_url.as_deref(),
+        Some("https://halcyondrift.bandcamp.com/track/low-tide")
+    );
+    assert_eq!(album.label.as_deref(), Some("Tidewater Records"));
+    assert_eq!(album.location.as_deref(), Some("Leeds, UK"));
+    assert_eq!(album.credits.as_deref(), Some("Mastered by J. Fenwick"));
+    assert!(album.about.is_some());
+    assert_eq!(
+        (
+            album.price,
+            album.currency.as_deref(),
+            album.name_your_price
+        ),
+        (Some(7.0), Some("GBP"), true)
+    );
+    let releases: Vec<String> = album.releases.iter().map(|r| r.to_string()).collect();
+    assert_eq!(releases, ["Digital 7.00 GBP", "Vinyl 22.00 GBP"]);
+    assert_eq!(parse_duration("P01H02M03S"), Some(3723.0));
+    assert_eq!(parse_duration("PT4M"), Some(240.0));
+
+    // Broken ld+json falls back to data-tralbum alone
+    let album = get_album(&Html::parse_document(&fixture("album_tralbum_only.html")))
+        .expect("Failed to fall back");
+    assert_eq!(album.id, 1144556677);
+    assert_eq!(album.artist, "The Paper Lanterns");
+    assert_eq!(album.tracks[&4002].name, "Attic:Window");
+    assert_eq!(album.strategies, [Strategy::DataTralbum]);
+    assert_eq!(album.credits.as_deref(), Some("Cello by R. Hale"));
+    assert!(album.name_your_price);
+    assert_eq!(album.releases[0].to_string(), "Cassette 8.00 USD");
+
+    let track =
+        get_album(&Html::parse_document(&fixture("track.html"))).expect("Failed to get track");
+    assert_eq!(track.id, 918273645);
+    assert_eq!(track.tracks[&2001].name, "Lone Single");
+    assert!(track.tracks[&2001].valid());
+    assert_eq!(track.tracks[&2001].duration, Some(192.0));
+    assert_eq!(track.strategies, [Strategy::LdJson, Strategy::DataTralbum]);
+
+    assert!(matches!(
+        get_album(&Html::parse_document(&fixture("track_no_stream.html"))),
+        Err(Error::NoStreamUrl(_))
+    ));
+    assert!(matches!(
+        get_album(&Html::parse_document(&fixture("no_metadata.html"))),
+        Err(Error::ScrapeMissingField(_))
+    ));
+
+    let server = MockServer::start([(
+        "/halcyondrift/album/night-shapes",
+        vec![MockResponse::ok(fixture("album.html"))],
+    )]);
+    let album = fetch_album(&server.url("/halcyondrift/album/night-shapes"))
+        .expect("Failed to fetch Album");
+    assert_eq!(album.tracks.len(), 3);
+    assert!(matches!(
+        fetch_album(&server.url("/missing")),
+        Err(Error::HttpStatus(404))
+    ));
 }
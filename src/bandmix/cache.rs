@@ -0,0 +1,273 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use directories::ProjectDirs;
+use stream_download::storage::StorageProvider;
+use tracing::{debug, info, warn};
+
+use crate::bandcamp::models::TrackID;
+
+const AUDIO_EXTENSION: &str = "mp3";
+const PART_EXTENSION: &str = "part";
+
+struct CacheEntry {
+    size: u64,
+    last_access: SystemTime,
+}
+
+/// Persistent audio cache keyed by [`TrackID`], evicting the least recently played tracks
+/// once the total size goes over `max_bytes`.
+pub struct AudioCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    entries: Mutex<HashMap<TrackID, CacheEntry>>,
+    downloads: AtomicUsize,
+}
+
+impl AudioCache {
+    pub fn default_dir() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "bandmix").map(|dirs| dirs.cache_dir().join("audio"))
+    }
+
+    pub fn new(dir: PathBuf, max_bytes: u64) -> io::Result<AudioCache> {
+        fs::create_dir_all(&dir)?;
+
+        let mut entries = HashMap::new();
+        for file in fs::read_dir(&dir)?.flatten() {
+            let path = file.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            if extension == Some(PART_EXTENSION) {
+                // Left over from a download that never finished
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if extension != Some(AUDIO_EXTENSION) {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<TrackID>().ok())
+            else {
+                continue;
+            };
+            let Ok(metadata) = file.metadata() else {
+                continue;
+            };
+            entries.insert(
+                id,
+                CacheEntry {
+                    size: metadata.len(),
+                    last_access: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                },
+            );
+        }
+
+        let cache = AudioCache {
+            dir,
+            max_bytes,
+            entries: Mutex::new(entries),
+            downloads: AtomicUsize::new(0),
+        };
+        cache.evict();
+        info!(
+            "Audio cache at {} : {} tracks, {} MB",
+            cache.dir.display(),
+            cache.len(),
+            cache.size() / 1_000_000
+        );
+        Ok(cache)
+    }
+
+    fn path(&self, id: TrackID) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, AUDIO_EXTENSION))
+    }
+
+    /// Every download gets its own file so replaying a track that is still downloading is safe
+    fn part_path(&self, id: TrackID) -> PathBuf {
+        let download = self.downloads.fetch_add(1, Relaxed);
+        self.dir
+            .join(format!("{}.{}.{}", id, download, PART_EXTENSION))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|e| e.len()).unwrap_or_default()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.entries
+            .lock()
+            .map(|e| e.values().map(|c| c.size).sum())
+            .unwrap_or_default()
+    }
+
+    /// Path to the cached audio of a track, marking it as recently used.
+    pub fn get(&self, id: TrackID) -> Option<PathBuf> {
+        let mut entries = self.entries.lock().ok()?;
+        let entry = entries.get_mut(&id)?;
+        let path = self.path(id);
+        if !path.exists() {
+            entries.remove(&id);
+            return None;
+        }
+        entry.last_access = SystemTime::now();
+        // Keep the access time across restarts
+        if let Err(error) = File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(entry.last_access))
+        {
+            debug!("Failed to touch cached track {} : {}", id, error);
+        }
+        Some(path)
+    }
+
+    /// Storage used to download a track, which becomes part of the cache once [`commit`]ed.
+    ///
+    /// [`commit`]: AudioCache::commit
+    pub fn storage(&self, id: TrackID) -> CacheStorageProvider {
+        CacheStorageProvider {
+            part: self.part_path(id),
+        }
+    }
+
+    /// Move a fully downloaded track into the cache.
+    pub fn commit(&self, id: TrackID, part: &Path, expected_size: Option<u64>) {
+        let size = match fs::metadata(part) {
+            Ok(metadata) => metadata.len(),
+            Err(error) => {
+                warn!("Failed to read downloaded track {} : {}", id, error);
+                return;
+            }
+        };
+        if expected_size.is_some_and(|expected| expected != size) {
+            warn!("Downloaded track {} is incomplete, not caching", id);
+            return;
+        }
+        if let Err(error) = fs::rename(part, self.path(id)) {
+            warn!("Failed to cache track {} : {}", id, error);
+            return;
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(
+                id,
+                CacheEntry {
+                    size,
+                    last_access: SystemTime::now(),
+                },
+            );
+        }
+        debug!("Cached track {}", id);
+        self.evict();
+    }
+
+    fn evict(&self) {
+        let Ok(mut entries) = self.entries.lock() else {
+            warn!("Failed to lock audio cache");
+            return;
+        };
+        let mut total: u64 = entries.values().map(|e| e.size).sum();
+        if total <= self.max_bytes {
+            return;
+        }
+        let mut oldest: Vec<(TrackID, SystemTime, u64)> = entries
+            .iter()
+            .map(|(id, e)| (*id, e.last_access, e.size))
+            .collect();
+        oldest.sort_by_key(|(_, last_access, _)| *last_access);
+
+        for (id, _, size) in oldest {
+            if total <= self.max_bytes {
+                break;
+            }
+            if let Err(error) = fs::remove_file(self.path(id)) {
+                warn!("Failed to evict cached track {} : {}", id, error);
+            }
+            entries.remove(&id);
+            total -= size;
+            debug!("Evicted cached track {}", id);
+        }
+    }
+}
+
+/// Downloads into a `.part` file inside the cache directory, which is removed again if the
+/// download is dropped before being committed.
+#[derive(Clone, Debug)]
+pub struct CacheStorageProvider {
+    part: PathBuf,
+}
+
+impl CacheStorageProvider {
+    pub fn part(&self) -> &Path {
+        &self.part
+    }
+}
+
+impl StorageProvider for CacheStorageProvider {
+    type Reader = CacheStorageReader;
+    type Writer = File;
+
+    fn into_reader_writer(
+        self,
+        _content_length: Option<u64>,
+    ) -> io::Result<(Self::Reader, Self::Writer)> {
+        let writer = File::create(&self.part)?;
+        let reader = CacheStorageReader {
+            reader: BufReader::new(File::open(&self.part)?),
+            part: self.part,
+        };
+        Ok((reader, writer))
+    }
+}
+
+pub struct CacheStorageReader {
+    reader: BufReader<File>,
+    part: PathBuf,
+}
+
+impl Read for CacheStorageReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Seek for CacheStorageReader {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(position)
+    }
+}
+
+impl Drop for CacheStorageReader {
+    fn drop(&mut self) {
+        if Path::exists(&self.part) {
+            let _ = fs::remove_file(&self.part);
+        }
+    }
+}
+
+#[test]
+fn test_cache_eviction() {
+    let dir = std::env::temp_dir().join(format!("bandmix-cache-test-{}", std::process::id()));
+    let cache = AudioCache::new(dir.clone(), 10).expect("Failed to create cache");
+
+    for id in 1..=3 {
+        let part = cache.part_path(id);
+        fs::write(&part, [0u8; 4]).expect("Failed to write part");
+        cache.commit(id, &part, Some(4));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(1).is_none(), "Oldest track should be evicted");
+    assert!(cache.get(3).is_some());
+    let _ = fs::remove_dir_all(dir);
+}
@@ -1,6 +1,7 @@
 use souvlaki::{MediaControls, PlatformConfig};
 
-pub fn get_media_controls() -> MediaControls {
+/// `dbus_name` is only used on Linux, where it names the MPRIS service
+pub fn get_media_controls(dbus_name: &str) -> MediaControls {
     #[cfg(not(target_os = "windows"))]
     let hwnd = None;
 
@@ -36,7 +37,7 @@ pub fn get_media_controls() -> MediaControls {
     };
 
     let config = PlatformConfig {
-        dbus_name: "bandmix",
+        dbus_name,
         display_name: "BandMix",
         hwnd,
     };
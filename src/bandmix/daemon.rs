@@ -0,0 +1,297 @@
+//! Line based control socket. Each request is a single line, `command [arguments]`, answered
+//! by any number of `key: value` lines followed by `OK` or `ERR <reason>`.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use directories::ProjectDirs;
+use souvlaki::MediaControlEvent;
+use tracing::{debug, info, warn};
+
+use super::{
+    discovery,
+    events::{Event, EventSender},
+    status::{self, format_time},
+};
+
+pub const SOCKET_NAME: &str = "bandmix.sock";
+
+pub fn default_socket() -> PathBuf {
+    ProjectDirs::from("", "", "bandmix")
+        .and_then(|dirs| dirs.runtime_dir().map(Path::to_path_buf))
+        .unwrap_or_else(std::env::temp_dir)
+        .join(SOCKET_NAME)
+}
+
+/// Run a single request, returning the lines to answer with before `OK`.
+pub fn handle(request: &str, send_event: &EventSender) -> Result<Vec<String>, String> {
+    let words: Vec<&str> = request.split_whitespace().collect();
+    let mut response = Vec::new();
+    match words.as_slice() {
+        ["play"] => send_event(MediaControlEvent::Play.into()),
+        ["pause"] => send_event(MediaControlEvent::Pause.into()),
+        ["toggle"] => send_event(MediaControlEvent::Toggle.into()),
+        ["next"] => send_event(MediaControlEvent::Next.into()),
+        ["previous"] => send_event(MediaControlEvent::Previous.into()),
+        ["quit"] => send_event(MediaControlEvent::Quit.into()),
+        ["volume"] => response.push(format!("volume: {:.0}", status::get().volume * 100.0)),
+        ["volume", volume] => {
+            let volume: f64 = volume
+                .trim_end_matches('%')
+                .parse()
+                .map_err(|_| format!("Invalid volume: {}", volume))?;
+            send_event(MediaControlEvent::SetVolume((volume / 100.0).clamp(0.0, 1.0)).into());
+        }
+        ["like"] => discovery::like_current_track().map_err(|error| error.to_string())?,
+        ["dislike"] => {
+            discovery::dislike_current_track().map_err(|error| error.to_string())?;
+            send_event(MediaControlEvent::Next.into());
+        }
+        ["unmark"] => discovery::unmark_current_track().map_err(|error| error.to_string())?,
+        ["status"] => {
+            let status = status::get();
+            response.extend([
+                format!("state: {}", status.state()),
+                format!("title: {}", status.track.name),
+                format!("artist: {}", status.track.artist),
+                format!("album: {}", status.track.album_name),
+                format!("album_artist: {}", status.track.album_artist),
+                format!("label: {}", status.track.label.clone().unwrap_or_default()),
+                format!("price: {}", status.track.price_text().unwrap_or_default()),
+                format!("position: {}", format_time(status.position)),
+                format!(
+                    "duration: {}",
+                    status.duration.map(format_time).unwrap_or_default()
+                ),
+                format!("volume: {:.0}", status.volume * 100.0),
+                format!("mode: {}", discovery::play_mode()),
+                format!("shuffle: {}", discovery::shuffle()),
+                format!("repeat: {}", discovery::repeat()),
+                format!("liked: {}", discovery::liked(status.track.id)),
+                format!("device: {}", status.device),
+            ]);
+            if let Some(error) = status.error {
+                response.push(format!("error: {}", error));
+            }
+        }
+        ["queue"] | ["queue", _] => {
+            let count = match words.get(1) {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| format!("Invalid count: {}", count))?,
+                None => 16,
+            };
+            response.extend(
+                discovery::upcoming(count)
+                    .into_iter()
+                    .map(|e| format!("{} - {} - {}", e.name, e.artist, e.album_name)),
+            );
+        }
+        ["mode"] => response.push(format!("mode: {}", discovery::play_mode())),
+        ["mode", mode] => discovery::set_play_mode(mode.parse()?),
+        ["shuffle"] => response.push(format!("shuffle: {}", discovery::shuffle())),
+        ["shuffle", shuffle] => discovery::set_shuffle(shuffle.parse()?),
+        ["repeat"] => discovery::set_repeat(discovery::repeat().next()),
+        ["repeat", repeat] => discovery::set_repeat(
+            repeat
+                .parse()
+                .map_err(|_| format!("Unknown repeat: {}", repeat))?,
+        ),
+        ["replay"] => send_event(Event::Jump {
+            position: discovery::album_start().ok_or("Nothing to replay")?,
+            mark_skipped: false,
+        }),
+        ["skip-album"] | ["skip-album", _] => {
+            let mark = match words.get(1) {
+                Some(mark) => Some(
+                    mark.parse()
+                        .map_err(|_| format!("Unknown mark: {}", mark))?,
+                ),
+                None => None,
+            };
+            let position = discovery::skip_album().map_err(|error| error.to_string())?;
+            send_event(Event::SkipAlbum { position, mark });
+        }
+        ["previous-album"] => send_event(Event::Jump {
+            position: discovery::previous_album().map_err(|error| error.to_string())?,
+            mark_skipped: false,
+        }),
+        ["lyrics"] => {
+            let track = status::get().track;
+            let lyrics = track.lyrics.ok_or("No lyrics for the current track")?;
+            response.extend(lyrics.lines().map(str::to_string));
+        }
+        [] => return Err("Empty request".to_string()),
+        _ => return Err(format!("Unknown command: {}", request.trim())),
+    }
+    Ok(response)
+}
+
+#[cfg(unix)]
+mod server {
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        path::{Path, PathBuf},
+        sync::Mutex,
+        thread,
+    };
+
+    use once_cell::sync::Lazy;
+    use tracing::{debug, info, warn};
+
+    use super::handle;
+    use crate::bandmix::events::EventSender;
+
+    static SOCKET: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(Default::default);
+
+    fn serve(stream: UnixStream, send_event: EventSender) {
+        let Ok(mut writer) = stream.try_clone() else {
+            warn!("Failed to clone control connection");
+            return;
+        };
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            debug!("Control request : {}", line);
+            let reply = match handle(&line, &send_event) {
+                Ok(lines) => lines.join("\n") + if lines.is_empty() { "OK\n" } else { "\nOK\n" },
+                Err(error) => format!("ERR {}\n", error),
+            };
+            if writer.write_all(reply.as_bytes()).is_err() {
+                break;
+            }
+        }
+    }
+
+    pub fn start(path: &Path, send_event: EventSender) -> std::io::Result<()> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("bandmix is already listening on {}", path.display()),
+                ));
+            }
+            // Stale socket from a previous run
+            fs::remove_file(path)?;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("Listening for control commands on {}", path.display());
+        if let Ok(mut socket) = SOCKET.lock() {
+            *socket = Some(path.to_path_buf());
+        }
+
+        thread::Builder::new()
+            .name("Control socket".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let send_event = send_event.clone();
+                            let _ = thread::Builder::new()
+                                .name("Control connection".to_string())
+                                .spawn(move || serve(stream, send_event));
+                        }
+                        Err(error) => warn!("Control connection failed : {}", error),
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    pub fn stop() {
+        let path = SOCKET.lock().ok().and_then(|mut s| s.take());
+        if let Some(path) = path {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    pub fn connect(path: &Path) -> std::io::Result<UnixStream> {
+        UnixStream::connect(path)
+    }
+}
+
+#[cfg(unix)]
+use server::connect;
+#[cfg(unix)]
+pub use server::{start, stop};
+
+#[cfg(not(unix))]
+pub fn start(_path: &Path, _send_event: EventSender) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The control socket is only available on unix",
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn stop() {}
+
+#[cfg(not(unix))]
+fn connect(_path: &Path) -> io::Result<std::net::TcpStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The control socket is only available on unix",
+    ))
+}
+
+/// Send one request to a running bandmix and print the answer, failing on `ERR`.
+pub fn ctl(path: &Path, request: &str) -> io::Result<()> {
+    let stream = connect(path)?;
+    let mut writer = stream.try_clone()?;
+    writer.write_all(format!("{}\n", request).as_bytes())?;
+    debug!("Sent control request : {}", request);
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line == "OK" {
+            return Ok(());
+        }
+        if let Some(error) = line.strip_prefix("ERR ") {
+            return Err(io::Error::other(error.to_string()));
+        }
+        println!("{}", line);
+    }
+    warn!("Control connection closed early");
+    info!("Is bandmix running with a control socket?");
+    Err(io::ErrorKind::UnexpectedEof.into())
+}
+
+#[test]
+fn test_handle() {
+    let _player_state = super::mock::lock_player_state();
+    let (send_event, sent) = super::mock::recorder();
+
+    assert_eq!(handle("next", &send_event), Ok(Vec::new()));
+    assert_eq!(handle(" volume 50% ", &send_event), Ok(Vec::new()));
+    assert!(handle("volume loud", &send_event).is_err());
+    assert!(handle("rewind", &send_event).is_err());
+    assert!(handle("skip-album sideways", &send_event).is_err());
+    assert!(handle("repeat sideways", &send_event).is_err());
+    assert!(handle("", &send_event).is_err());
+    assert_eq!(
+        *sent.lock().unwrap(),
+        ["UserSkip", "Control(SetVolume(0.5))"]
+    );
+
+    #[cfg(unix)]
+    {
+        let path = std::env::temp_dir().join(format!("bandmix-test-{}.sock", std::process::id()));
+        start(&path, send_event).expect("Failed to start control socket");
+        assert!(ctl(&path, "pause").is_ok());
+        assert!(ctl(&path, "rewind").is_err());
+        stop();
+        assert!(!path.exists());
+        assert_eq!(
+            sent.lock().unwrap().last().map(String::as_str),
+            Some("Control(Pause)")
+        );
+    }
+}
@@ -14,22 +14,196 @@ use std::{
 use crossbeam::queue::ArrayQueue;
 use dashmap::DashMap;
 use localsavefile::{localsavefile, LocalSaveFilePersistent};
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
 use sharded_slab::Slab;
+use thiserror::Error;
+use tokio::sync::Notify;
 use tracing::{debug, error, info, trace, warn};
 
+use super::shuffle::{Pending, Shuffle, Staging};
 use crate::bandcamp::{
     self,
-    api::{DiscoveryType, Format, Function, Genre, RecommendedType},
-    models::{Album, AlbumID, Track, TrackID},
+    api::{DiscoveryType, Format, Function, Genre, RecommendedType, Site},
+    models::{format_price, Album, AlbumID, Release, Track, TrackID, TrackNum},
 };
 
 type AlbumListens = BTreeSet<TrackID>;
 
+#[derive(Error, Debug)]
+pub enum DiscoveryError {
+    #[error("no current track")]
+    NoCurrentTrack,
+    #[error("cache error: {0}")]
+    Cache(String),
+    #[error("next album not loaded yet")]
+    NoNextAlbum,
+    #[error("no previous album")]
+    NoPreviousAlbum,
+    #[error("no album has track {0}")]
+    UnknownTrack(TrackID),
+    #[error("failed to reload album: {0}")]
+    Reload(#[from] bandcamp::Error),
+}
+
+/// Recorded against every track of an album skipped as a whole
+#[derive(Debug, Clone, Copy, PartialEq, strum::EnumString, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum AlbumMark {
+    /// Not queued again, as if played
+    Listened,
+    /// Not queued again, and counted as disliked
+    Disliked,
+}
+
+/// What discovery asks the feed for, how far ahead it loads and what it leaves out
+#[derive(Debug, Clone)]
+pub struct DiscoverySettings {
+    pub genre: Option<Genre>,
+    pub discovery_type: Option<DiscoveryType>,
+    pub format: Option<Format>,
+    pub recommended_type: Option<RecommendedType>,
+    /// Albums from the discover feed waiting for their tracks to be loaded
+    pub discovered_albums: usize,
+    /// Loaded albums waiting for their tracks to be queued
+    pub albums: usize,
+    /// Tracks queued ahead of the current one before loading slows down
+    pub slow_ahead: usize,
+    /// Tracks queued ahead of the current one before loading stops
+    pub max_ahead: usize,
+    /// Pause after each album once `slow_ahead` is reached
+    pub slow_delay: Duration,
+    /// How often a full queue is checked for room
+    pub poll: Duration,
+    /// Albums with any of these tags are not queued, compared ignoring case
+    pub exclude_tags: Vec<String>,
+    /// Artists not queued, compared ignoring case
+    pub exclude_artists: Vec<String>,
+    /// Queue tracks and albums that have already been listened to
+    pub replay_listened: bool,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        DiscoverySettings {
+            genre: None,
+            discovery_type: None,
+            format: None,
+            recommended_type: None,
+            discovered_albums: 32,
+            albums: 4,
+            slow_ahead: 8,
+            max_ahead: 32,
+            slow_delay: Duration::from_millis(2500),
+            poll: Duration::from_millis(500),
+            exclude_tags: Vec::new(),
+            exclude_artists: Vec::new(),
+            replay_listened: false,
+        }
+    }
+}
+
+/// Tracks per album played by [`PlayMode::Sampler`] when no count is given
+pub const SAMPLER_TRACKS: usize = 3;
+
+/// Which of each album's tracks are queued, and in what order
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PlayMode {
+    /// Every track in order
+    #[default]
+    Album,
+    /// Only the featured track
+    Featured,
+    /// The featured track, which needs no album page, then the rest in order
+    FeaturedFirst,
+    /// The first tracks in order
+    Sampler(usize),
+}
+
+impl PlayMode {
+    pub fn next(&self) -> PlayMode {
+        match self {
+            PlayMode::Album => PlayMode::Featured,
+            PlayMode::Featured => PlayMode::FeaturedFirst,
+            PlayMode::FeaturedFirst => PlayMode::Sampler(SAMPLER_TRACKS),
+            PlayMode::Sampler(_) => PlayMode::Album,
+        }
+    }
+}
+
+impl std::fmt::Display for PlayMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayMode::Album => f.write_str("album"),
+            PlayMode::Featured => f.write_str("featured"),
+            PlayMode::FeaturedFirst => f.write_str("featured-first"),
+            PlayMode::Sampler(count) => write!(f, "sampler:{}", count),
+        }
+    }
+}
+
+impl std::str::FromStr for PlayMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.split_once(':') {
+            None => match mode {
+                "album" => Ok(PlayMode::Album),
+                "featured" => Ok(PlayMode::Featured),
+                "featured-first" => Ok(PlayMode::FeaturedFirst),
+                "sampler" => Ok(PlayMode::Sampler(SAMPLER_TRACKS)),
+                _ => Err(format!("Unknown play mode: {}", mode)),
+            },
+            Some(("sampler", count)) => match count.parse() {
+                Ok(count) if count > 0 => Ok(PlayMode::Sampler(count)),
+                _ => Err(format!("Invalid sampler track count: {}", count)),
+            },
+            Some(_) => Err(format!("Unknown play mode: {}", mode)),
+        }
+    }
+}
+
+/// What plays once a track reaches its end
+#[derive(
+    Debug, Clone, Copy, PartialEq, Default, strum::EnumString, strum::Display, strum::EnumIter,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Repeat {
+    #[default]
+    Off,
+    /// The same track again
+    One,
+    /// The album's next track, back to its first after the last
+    Album,
+}
+
+impl Repeat {
+    pub fn next(&self) -> Repeat {
+        use strum::IntoEnumIterator;
+        let mut modes = Repeat::iter().cycle();
+        modes.find(|m| m == self);
+        modes.next().unwrap_or_default()
+    }
+}
+
+static REPEAT: Mutex<Repeat> = Mutex::new(Repeat::Off);
+static SKIP_ALBUM_MARK: Mutex<Option<AlbumMark>> = Mutex::new(None);
+static PLAY_MODE: Mutex<PlayMode> = Mutex::new(PlayMode::Album);
+static SHUFFLE: Mutex<Shuffle> = Mutex::new(Shuffle {
+    within_album: false,
+    interleave: 0,
+    artist_spacing: 0,
+});
+
+static SETTINGS: OnceCell<DiscoverySettings> = OnceCell::new();
+
 static DISCOVERY_STATE: Lazy<Arc<AtomicBool>> = Lazy::new(|| Arc::new(AtomicBool::new(false)));
 
-static ALBUM_URL_QUEUE: Lazy<ArrayQueue<String>> = Lazy::new(|| ArrayQueue::new(32));
-static ALBUM_QUEUE: Lazy<ArrayQueue<AlbumID>> = Lazy::new(|| ArrayQueue::new(4));
+/// Albums as the discover feed gives them, before any page is fetched
+static DISCOVERED_QUEUE: Lazy<ArrayQueue<Album>> =
+    Lazy::new(|| ArrayQueue::new(settings().discovered_albums.max(1)));
+static ALBUM_QUEUE: Lazy<ArrayQueue<AlbumID>> =
+    Lazy::new(|| ArrayQueue::new(settings().albums.max(1)));
 static ALBUM_MAP: Lazy<DashMap<AlbumID, Album>> = Lazy::new(DashMap::new);
 static ALBUM_LISTENS: Lazy<DashMap<AlbumID, AlbumListens>> = Lazy::new(DashMap::new);
 
@@ -43,16 +217,25 @@ static FILTERED_TRACK_INDEX_CAP: Lazy<Arc<AtomicUsize>> =
 static THREADS: Lazy<ArrayQueue<JoinHandle<()>>> = Lazy::new(|| ArrayQueue::new(3));
 
 // TODO: Expose cache and only add to it when a song has been 'listened' to
-static mut DATA_CACHE: Lazy<Mutex<TrackCache>> =
-    Lazy::new(|| Mutex::new(TrackCache::load_default()));
+static DATA_CACHE: Lazy<Mutex<TrackCache>> = Lazy::new(|| Mutex::new(TrackCache::load_default()));
 pub static TRACK_CURSOR: AtomicUsize = AtomicUsize::new(0);
+/// Woken whenever a track is added to the track list
+static TRACK_ADDED: Lazy<Notify> = Lazy::new(Notify::new);
 
-#[localsavefile(persist = true, version = 1)]
+#[localsavefile(persist = true, version = 4)]
 struct TrackCache {
     last_cursor: usize,
     track_ids: HashSet<u32>,
     #[savefile_versions = "1.."]
     album_ids: HashSet<u32>,
+    #[savefile_versions = "2.."]
+    liked_ids: HashSet<u32>,
+    #[savefile_versions = "2.."]
+    disliked_ids: HashSet<u32>,
+    #[savefile_versions = "3.."]
+    skipped_ids: HashSet<u32>,
+    #[savefile_versions = "4.."]
+    failed_ids: HashSet<u32>,
 }
 
 fn album_listened(album: &Album) -> bool {
@@ -66,46 +249,69 @@ fn album_listened(album: &Album) -> bool {
     album.tracks.values().all(|t| listens.contains(&t.id))
 }
 
-fn add_listened_track(track: &Track) {
+fn add_listened_track(track: &Track) -> Result<(), DiscoveryError> {
     // FIXME: will get_mut cause deadlock issues here?
     if let Some(mut listens) = ALBUM_LISTENS.get_mut(&track.album_id) {
         listens.insert(track.id);
     } else {
         warn!("Failed to get album listen entry for track");
     };
-    if let Ok(mut tc) = unsafe { DATA_CACHE.lock() } {
+    update_data_cache(|tc| {
         tc.track_ids.insert(track.id);
-        if tc.save().is_err() {
-            warn!("Failed to save track cache");
-        }
-    } else {
-        warn!("Failed to lock data cache");
-    }
+    })
 }
 
-fn add_listened_album(album: &Album) {
-    if let Ok(mut tc) = unsafe { DATA_CACHE.lock() } {
+fn add_listened_album(album: &Album) -> Result<(), DiscoveryError> {
+    update_data_cache(|tc| {
         tc.album_ids.insert(album.id);
-        if tc.save().is_err() {
-            warn!("Failed to save album cache");
-        }
-    } else {
-        warn!("Failed to lock data cache");
+    })
+}
+
+/// Record a track as listened, along with its album once every track has been
+fn add_listened(album: &Album, track: &Track) -> Result<(), DiscoveryError> {
+    add_listened_track(track)?;
+    if album_listened(album) {
+        add_listened_album(album)?;
     }
+    Ok(())
+}
+
+fn settings() -> &'static DiscoverySettings {
+    SETTINGS.get_or_init(DiscoverySettings::default)
 }
 
 fn filtered_track(track: &Track) -> bool {
-    // TODO: genre blacklist
-    if let Ok(tc) = unsafe { DATA_CACHE.lock() } {
-        tc.track_ids.contains(&track.id)
+    if let Ok(tc) = DATA_CACHE.lock() {
+        (tc.track_ids.contains(&track.id) && !settings().replay_listened)
+            || tc.failed_ids.contains(&track.id)
     } else {
         warn!("Failed to lock data cache");
         false
     }
 }
 
+/// Tagged or by an artist the settings exclude
+fn excluded_album(album: &Album, settings: &DiscoverySettings) -> bool {
+    let matches = |excluded: &[String], value: &str| {
+        excluded
+            .iter()
+            .any(|excluded| excluded.eq_ignore_ascii_case(value.trim()))
+    };
+    matches(&settings.exclude_artists, &album.artist)
+        || album.tags.as_deref().is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| matches(&settings.exclude_tags, tag))
+        })
+}
+
 fn filtered_album(album: &Album) -> bool {
-    if let Ok(tc) = unsafe { DATA_CACHE.lock() } {
+    if excluded_album(album, settings()) {
+        return true;
+    }
+    if settings().replay_listened {
+        return false;
+    }
+    if let Ok(tc) = DATA_CACHE.lock() {
         tc.album_ids.contains(&album.id)
     } else {
         warn!("Failed to lock data cache");
@@ -113,89 +319,256 @@ fn filtered_album(album: &Album) -> bool {
     }
 }
 
-fn discovery_load_album_urls_task(function: &Function) -> Option<()> {
-    let query = bandcamp::api::DISCOVER_API.build_query(function).ok()?;
-    debug!("Obtaining URLs : {}", query);
-    let result = bandcamp::api::Api::request(query).ok()?;
-    let item = gjson::get(&result, "@this.items.#(type=a)#.url_hints");
+/// Albums in a discover response, each with only what the feed gives
+fn discovered_albums(json: &str, site: &Site) -> Vec<Album> {
+    gjson::get(json, "@this.items.#(type=a)#")
+        .array()
+        .iter()
+        .filter_map(|item| {
+            let hints = item.get("url_hints");
+            let url = site.album_url(hints.get("subdomain").str(), hints.get("slug").str());
+            match bandcamp::spider::discover_album(item, url) {
+                Ok(album) => Some(album),
+                Err(error) => {
+                    warn!("Skipping discover item : {}", error);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn discovery_load_album_urls_task(function: &Function, site: &Site) -> bandcamp::Result<()> {
+    let query = site.discover_api().build_query(function)?;
+    debug!("Obtaining albums : {}", query);
+    let result = bandcamp::api::Api::request(query)?;
 
-    item.each(|_, value: gjson::Value| {
-        let subdomain = value.get("subdomain").to_string();
-        let slug = value.get("slug").to_string();
-        if ALBUM_URL_QUEUE
-            .push(format!("https://{}.bandcamp.com/album/{}", subdomain, slug))
-            .is_err()
-        {
-            warn!("Error pushing to Album URL queue");
+    for album in discovered_albums(&result, site) {
+        if DISCOVERED_QUEUE.push(album).is_err() {
+            warn!("Error pushing to discovered Album queue");
         }
-        if ALBUM_URL_QUEUE.is_full() {
-            trace!("Album URL queue full, waiting");
-            while ALBUM_URL_QUEUE.is_full() {
+        if DISCOVERED_QUEUE.is_full() {
+            trace!("Discovered Album queue full, waiting");
+            while DISCOVERED_QUEUE.is_full() {
                 if !DISCOVERY_STATE.load(Relaxed) {
                     break;
                 }
-                sleep(Duration::from_millis(1000));
+                sleep(settings().poll);
             }
         }
-        DISCOVERY_STATE.load(Relaxed) // keep iterating
-    });
-    Some(())
+        if !DISCOVERY_STATE.load(Relaxed) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Scrape the page of an album known only from the discover feed, keeping what the feed
+/// gave where the page has nothing
+fn scrape_album(discovered: Album) -> bandcamp::Result<Album> {
+    let mut album = bandcamp::spider::fetch_album(&discovered.url)?;
+    if album.id != discovered.id {
+        warn!(
+            "Album {} has id {} on its page, not {}",
+            album.name, album.id, discovered.id
+        );
+        album.id = discovered.id;
+    }
+    bandcamp::spider::merge(&mut album, discovered);
+    Ok(album)
+}
+
+/// Swap an album in the map for its scraped page, leaving it as it was if that fails
+fn complete_album(id: AlbumID) {
+    let Some(discovered) = ALBUM_MAP.get(&id).map(|album| album.clone()) else {
+        return;
+    };
+    if discovered.complete() {
+        return;
+    }
+    let name = discovered.name.clone();
+    match scrape_album(discovered) {
+        Ok(album) => {
+            ALBUM_MAP.insert(id, album);
+        }
+        Err(error) => warn!("Failed to load the rest of {} : {}", name, error),
+    }
+}
+
+/// Scrape the page of a track's album again for fresh stream urls, as Bandcamp's expire
+pub fn reload_album_of(id: TrackID) -> Result<(), DiscoveryError> {
+    let album = ALBUM_MAP
+        .iter()
+        .find(|album| album.tracks.contains_key(&id))
+        .map(|album| album.clone())
+        .ok_or(DiscoveryError::UnknownTrack(id))?;
+    let album = scrape_album(album)?;
+    ALBUM_MAP.insert(album.id, album);
+    Ok(())
 }
 
 fn discovery_load_albums_job() {
     while DISCOVERY_STATE.load(Relaxed) {
-        if ALBUM_URL_QUEUE.is_empty() {
-            trace!("Album URL Queue empty, waiting");
+        if DISCOVERED_QUEUE.is_empty() {
+            trace!("Discovered Album queue empty, waiting");
         }
-        while ALBUM_URL_QUEUE.is_empty() {
+        while DISCOVERED_QUEUE.is_empty() {
             if !DISCOVERY_STATE.load(Relaxed) {
                 return;
             }
             sleep(Duration::from_millis(100));
         }
-        let url = match ALBUM_URL_QUEUE.pop() {
-            Some(u) => u,
-            None => {
-                warn!("Failed to pop album url");
-                continue;
-            }
+        let Some(mut album) = DISCOVERED_QUEUE.pop() else {
+            warn!("Failed to pop discovered album");
+            continue;
         };
-        let album = bandcamp::spider::fetch_album(&url);
-        if let Some(album) = album {
-            trace!("Processing Album : {}", album.name);
-            if filtered_album(&album) {
-                // TODO: Latently prepend filtered albums onto master track list
-                info!("Filtered Album: {}", album.name);
-            } else {
-                let id = album.id;
-                if ALBUM_MAP.insert(id, album).is_some() {
-                    warn!("Album collision pushing to map : {}", &id);
-                }
-                if ALBUM_LISTENS.insert(id, Default::default()).is_some() {
-                    warn!("Album collision pushing to map : {}", &id);
-                }
-                if let Err(error) = ALBUM_QUEUE.push(id) {
-                    warn!("Album error pushing to queue : {}", error);
+        trace!("Processing Album : {}", album.name);
+        if filtered_album(&album) {
+            // TODO: Latently prepend filtered albums onto master track list
+            info!("Filtered Album: {}", album.name);
+            continue;
+        }
+        // Without a featured track to start on, there is nothing to play until the page is in
+        if !album.tracks.values().any(Track::valid) {
+            let url = album.url.clone();
+            album = match scrape_album(album) {
+                Ok(album) => album,
+                Err(error) => {
+                    warn!("Failed to fetch Album {} : {}", url, error);
+                    continue;
                 }
-            }
-        } else {
-            warn!("Failed to fetch Album : {}", url);
+            };
+        }
+        let id = album.id;
+        if ALBUM_MAP.insert(id, album).is_some() {
+            warn!("Album collision pushing to map : {}", &id);
         }
-        // TODO: How much should we wait?
-        if DISCOVERY_STATE.load(Relaxed) && (ALBUM_QUEUE.len() > 1) {
-            sleep(Duration::from_millis(2500));
+        if ALBUM_LISTENS.insert(id, Default::default()).is_some() {
+            warn!("Album collision pushing to map : {}", &id);
+        }
+        if let Err(error) = ALBUM_QUEUE.push(id) {
+            warn!("Album error pushing to queue : {}", error);
         }
         if ALBUM_QUEUE.is_full() {
             trace!("Album Queue full, waiting");
             while ALBUM_QUEUE.is_full() && DISCOVERY_STATE.load(Relaxed) {
-                sleep(Duration::from_millis(500));
+                sleep(settings().poll);
             }
         }
     }
     trace!("Stopping albums job");
 }
 
+pub fn play_mode() -> PlayMode {
+    PLAY_MODE.lock().map(|mode| *mode).unwrap_or_default()
+}
+
+/// Takes effect from the next album added to the track list
+pub fn set_play_mode(mode: PlayMode) {
+    match PLAY_MODE.lock() {
+        Ok(mut current) => *current = mode,
+        Err(_) => warn!("Failed to lock play mode"),
+    }
+    info!("Play mode: {}", mode);
+}
+
+/// The track marked as featured, or the first one when none is or it cannot play
+fn featured_track(album: &Album) -> Option<&Track> {
+    let mut playable = album.tracks.values().filter(|track| track.valid());
+    album
+        .featured_track_num
+        .and_then(|num| playable.clone().find(|track| track.num == num))
+        .or_else(|| playable.next())
+}
+
+/// The album's playable tracks that `mode` plays, in order
+fn selected_tracks(album: &Album, mode: PlayMode) -> Vec<&Track> {
+    let playable = album.tracks.values().filter(|track| track.valid());
+    match mode {
+        PlayMode::Album | PlayMode::FeaturedFirst => playable.collect(),
+        PlayMode::Featured => featured_track(album).into_iter().collect(),
+        PlayMode::Sampler(count) => playable.take(count).collect(),
+    }
+}
+
+pub fn shuffle() -> Shuffle {
+    SHUFFLE.lock().map(|shuffle| *shuffle).unwrap_or_default()
+}
+
+/// Takes effect from the next tracks added to the track list
+pub fn set_shuffle(shuffle: Shuffle) {
+    match SHUFFLE.lock() {
+        Ok(mut current) => *current = shuffle,
+        Err(_) => warn!("Failed to lock shuffle"),
+    }
+    info!("Shuffle: {}", shuffle);
+}
+
+/// Add a track to the end of the track list, only to the master list if filtered
+fn push_track(album_id: AlbumID, track_id: TrackID, filtered: bool) {
+    if let Some(index) = MASTER_TRACK_LIST.insert((album_id, track_id)) {
+        if !filtered {
+            if let Some(i) = FILTERED_TRACK_INDEX.insert(index) {
+                FILTERED_TRACK_INDEX_CAP.store(i, Relaxed);
+                TRACK_ADDED.notify_waiters();
+            } else {
+                warn!("Failed to insert into filtered track list");
+            }
+        }
+    } else {
+        warn!("Failed to insert into master track list");
+    }
+}
+
+/// Stage the tracks `mode` plays that are not already `queued`, returning how many were
+/// filtered. Filtered tracks go straight to the master list.
+fn stage_album_tracks(
+    id: AlbumID,
+    mode: PlayMode,
+    queued: &mut BTreeSet<TrackID>,
+    staging: &mut Staging,
+) -> usize {
+    let Some(album) = ALBUM_MAP.get(&id) else {
+        warn!("Failed to get album map for tracks");
+        return 0;
+    };
+    let mut filtered_count: usize = 0;
+    let mut tracks = Vec::new();
+
+    for track in selected_tracks(&album, mode) {
+        if queued.insert(track.id) {
+            if filtered_track(track) {
+                filtered_count += 1;
+                debug!("Filtered Track: {}", track.name);
+                push_track(album.id, track.id, true);
+            } else {
+                tracks.push(Pending {
+                    album_id: album.id,
+                    track_id: track.id,
+                    artist: track.artist.clone().unwrap_or_else(|| album.artist.clone()),
+                });
+            }
+        }
+    }
+    staging.add(album.id, tracks, &shuffle());
+    filtered_count
+}
+
+/// Move staged tracks onto the track list once there are enough to shuffle, or sooner when
+/// playback would otherwise run out
+fn flush_staging(staging: &mut Staging) {
+    let shuffle = shuffle();
+    let starving = playlist_length() <= position() + 1;
+    if !staging.ready(&shuffle) && !starving {
+        return;
+    }
+    for track in staging.take(&shuffle, starving) {
+        push_track(track.album_id, track.track_id, false);
+    }
+}
+
 fn discovery_load_tracks_job() {
+    let mut staging = Staging::default();
     while DISCOVERY_STATE.load(Relaxed) {
         if ALBUM_QUEUE.is_empty() {
             trace!("Album Queue empty, waiting");
@@ -204,48 +577,49 @@ fn discovery_load_tracks_job() {
             if !DISCOVERY_STATE.load(Relaxed) {
                 return;
             }
+            flush_staging(&mut staging);
             sleep(Duration::from_millis(100));
         }
-        let Some(album) = ALBUM_QUEUE.pop() else {
+        let Some(id) = ALBUM_QUEUE.pop() else {
             warn!("Failed to pop album for tracks");
             continue;
         };
-        let Some(album) = ALBUM_MAP.get(&album) else {
+
+        let mode = play_mode();
+        let mut queued = BTreeSet::new();
+        let mut filtered_count = 0;
+        // Only these modes put the featured track from the discover feed ahead of the rest,
+        // so it plays while the album loads; otherwise album order is kept
+        if matches!(mode, PlayMode::Featured | PlayMode::FeaturedFirst) {
+            filtered_count += stage_album_tracks(id, PlayMode::Featured, &mut queued, &mut staging);
+            flush_staging(&mut staging);
+        }
+        if mode != PlayMode::Featured {
+            complete_album(id);
+            // The page can carry tags the discover feed did not
+            if ALBUM_MAP
+                .get(&id)
+                .is_some_and(|album| excluded_album(&album, settings()))
+            {
+                info!("Excluded the rest of album {}", id);
+                continue;
+            }
+            filtered_count += stage_album_tracks(id, mode, &mut queued, &mut staging);
+            flush_staging(&mut staging);
+        }
+
+        let Some(album) = ALBUM_MAP.get(&id) else {
             warn!("Failed to get album map for tracks");
             continue;
         };
-
-        trace!("Processing Tracks : {}", album.name);
+        trace!("Processed Tracks : {}", album.name);
         let track_count: usize = album.tracks.len();
-        let mut filtered_count: usize = 0;
-
-        for track in album.tracks.values() {
-            if track.valid() {
-                let filtered = filtered_track(track);
-                if filtered {
-                    filtered_count += 1;
-                    debug!("Filtered Track: {}", track.name);
-                }
-                if let Some(index) = MASTER_TRACK_LIST.insert((album.id, track.id)) {
-                    if !filtered {
-                        if let Some(i) = FILTERED_TRACK_INDEX.insert(index) {
-                            FILTERED_TRACK_INDEX_CAP.store(i, Relaxed);
-                        } else {
-                            warn!("Failed to insert into filtered track list");
-                        }
-                    }
-                } else {
-                    warn!("Failed to insert into master track list");
-                }
-            }
-            if !DISCOVERY_STATE.load(Relaxed) {
-                break;
-            }
-        }
 
         if track_count == filtered_count {
             debug!("Added filtered Album: {}", album.name);
-            add_listened_album(&album);
+            if let Err(error) = add_listened_album(&album) {
+                warn!("Failed to add filtered Album : {}", error);
+            }
             info!(
                 "Filtered all tracks from {} by {}",
                 &album.name, &album.artist
@@ -259,33 +633,36 @@ fn discovery_load_tracks_job() {
                 &album.artist
             );
         }
+        drop(album);
 
+        let settings = settings();
         let cap = FILTERED_TRACK_INDEX_CAP.load(Relaxed);
         let mut cur = TRACK_CURSOR.load(Relaxed);
 
         // TODO: when should we wait?
-        if DISCOVERY_STATE.load(Relaxed) && (cur < cap) && (cap - cur > 8) {
-            sleep(Duration::from_millis(2500));
+        if DISCOVERY_STATE.load(Relaxed) && (cur < cap) && (cap - cur > settings.slow_ahead) {
+            sleep(settings.slow_delay);
         }
 
-        if (cur < cap) && (cap - cur > 32) {
+        if (cur < cap) && (cap - cur > settings.max_ahead) {
             trace!("Track List at capacity, waiting");
-            while (cur >= cap) || ((cap - cur > 32) && DISCOVERY_STATE.load(Relaxed)) {
+            while (cur >= cap)
+                || ((cap - cur > settings.max_ahead) && DISCOVERY_STATE.load(Relaxed))
+            {
                 cur = TRACK_CURSOR.load(Relaxed);
-                sleep(Duration::from_millis(500));
+                sleep(settings.poll);
             }
         }
     }
     trace!("Stopping tracks job");
 }
 
-fn discovery_page_urls_job(mut function: Function) {
+fn discovery_page_urls_job(mut function: Function, site: Site) {
     let mut page = 0;
 
     while DISCOVERY_STATE.load(Relaxed) {
-        let album_url_task = discovery_load_album_urls_task(&function);
-        if album_url_task.is_none() {
-            warn!("Album Url Task failed");
+        if let Err(error) = discovery_load_album_urls_task(&function, &site) {
+            warn!("Album Url Task failed : {}", error);
         }
         page += 1;
         function.update_get_web_page(page);
@@ -293,20 +670,25 @@ fn discovery_page_urls_job(mut function: Function) {
     trace!("Stopping urls job");
 }
 
-pub fn start(
-    genre: Option<Genre>,
-    discovery_type: Option<DiscoveryType>,
-    format: Option<Format>,
-    recommended_type: Option<RecommendedType>,
-) {
+pub fn start(site: Site, settings: DiscoverySettings) {
     if DISCOVERY_STATE.load(SeqCst) {
         error!("Discover already set, ensure no other tasks are running");
     }
     DISCOVERY_STATE.store(true, SeqCst);
+    if SETTINGS.set(settings).is_err() {
+        warn!("Discovery already configured");
+    }
+    let settings = self::settings();
 
     // TODO: option to store cursor position
 
-    let function = Function::get_web(0, genre, discovery_type, format, recommended_type);
+    let function = Function::get_web(
+        0,
+        settings.genre,
+        settings.discovery_type,
+        settings.format,
+        settings.recommended_type,
+    );
     let tasks = vec![
         thread::Builder::new()
             .name("Discovery load Albums".to_string())
@@ -316,7 +698,7 @@ pub fn start(
             .spawn(discovery_load_tracks_job),
         thread::Builder::new()
             .name("Discovery load URLs".to_string())
-            .spawn(move || discovery_page_urls_job(function)),
+            .spawn(move || discovery_page_urls_job(function, site)),
     ];
 
     let mut error = false;
@@ -334,13 +716,49 @@ pub fn start(
     }
 }
 
-#[derive(Default, PartialEq)]
+#[derive(Default, PartialEq, Clone, Serialize)]
 pub struct Entry {
+    pub id: TrackID,
+    pub num: TrackNum,
     pub name: String,
+    /// The track's own artist where credited, otherwise the album's
     pub artist: String,
+    pub album_artist: String,
     pub album_name: String,
     pub album_art_url: Option<String>,
+    pub release_date: String,
+    pub tags: Option<String>,
     pub url: String,
+    /// Seconds
+    pub duration: Option<f64>,
+    pub lyrics: Option<String>,
+    pub has_lyrics: bool,
+    pub streaming: bool,
+    pub downloadable: bool,
+    pub page_url: Option<String>,
+    pub art_url: Option<String>,
+    pub label: Option<String>,
+    pub location: Option<String>,
+    /// Of the digital album
+    pub price: Option<f64>,
+    pub currency: Option<String>,
+    pub name_your_price: bool,
+    pub about: Option<String>,
+    pub credits: Option<String>,
+    pub releases: Vec<Release>,
+}
+
+impl Entry {
+    /// What the digital album costs, such as `7.00 GBP or more`
+    pub fn price_text(&self) -> Option<String> {
+        let price = self.price?;
+        let text = format_price(price, self.currency.as_deref());
+        Some(match self.name_your_price {
+            true if price <= 0.0 => "name your price".to_string(),
+            true => text + " or more",
+            false => text,
+        })
+    }
 }
 
 impl std::fmt::Display for Entry {
@@ -352,50 +770,323 @@ impl std::fmt::Display for Entry {
     }
 }
 
+fn entry_at(track_i: usize) -> Option<Entry> {
+    let track_fi = *FILTERED_TRACK_INDEX.get(track_i)?;
+    let ids = MASTER_TRACK_LIST.get(track_fi)?;
+    let album = ALBUM_MAP.get(&ids.0)?;
+    let track = album.tracks.get(&ids.1)?;
+
+    Some(Entry {
+        id: track.id,
+        num: track.num,
+        name: track.name.clone(),
+        artist: track.artist.clone().unwrap_or_else(|| album.artist.clone()),
+        album_artist: album.artist.clone(),
+        album_name: album.name.clone(),
+        album_art_url: album.album_art_url.clone(),
+        release_date: album.release_date.clone(),
+        tags: album.tags.clone(),
+        url: track.url.clone(),
+        duration: track.duration,
+        lyrics: track.lyrics.clone(),
+        has_lyrics: track.has_lyrics,
+        streaming: track.streaming,
+        downloadable: track.downloadable,
+        page_url: track.page_url.clone(),
+        art_url: track.art_url.clone(),
+        label: album.label.clone(),
+        location: album.location.clone(),
+        price: album.price,
+        currency: album.currency.clone(),
+        name_your_price: album.name_your_price,
+        about: album.about.clone(),
+        credits: album.credits.clone(),
+        releases: album.releases.clone(),
+    })
+}
+
+/// The track at `track_i`, or `None` while it has yet to load, which [`wait_for`] waits out
 fn get_entry(track_i: usize) -> Option<Entry> {
-    // TODO: Handle waiting better
-    while FILTERED_TRACK_INDEX_CAP.load(Relaxed) <= track_i {
-        sleep(Duration::from_millis(10));
+    let track = entry_at(track_i);
+    debug!("Cursor now at : {}", TRACK_CURSOR.load(Relaxed));
+
+    if track.is_none() {
+        debug!("Entry {} not loaded yet", track_i);
+    }
+    track
+}
+
+/// Whether the track at `track_i` is on the track list yet
+pub fn loaded(track_i: usize) -> bool {
+    track_i < playlist_length()
+}
+
+/// Wait until the track at `track_i` is on the track list, without holding up the runtime
+pub async fn wait_for(track_i: usize) {
+    loop {
+        // Created before checking, so a track added in between still wakes it
+        let added = TRACK_ADDED.notified();
+        if loaded(track_i) {
+            return;
+        }
+        added.await;
     }
+}
+
+fn with_current_track<T>(f: impl FnOnce(&Album, &Track) -> T) -> Result<T, DiscoveryError> {
+    with_track_at(TRACK_CURSOR.load(Relaxed), f)
+}
 
-    let track = {
+fn with_track_at<T>(
+    track_i: usize,
+    f: impl FnOnce(&Album, &Track) -> T,
+) -> Result<T, DiscoveryError> {
+    let current = || {
         let track_fi = *FILTERED_TRACK_INDEX.get(track_i)?;
         let ids = MASTER_TRACK_LIST.get(track_fi)?;
         let album = ALBUM_MAP.get(&ids.0)?;
         let track = album.tracks.get(&ids.1)?;
-        debug!("Cursor now at : {}", TRACK_CURSOR.load(Relaxed));
-
-        Some(Entry {
-            name: track.name.clone(),
-            artist: album.artist.clone(),
-            album_name: album.name.clone(),
-            album_art_url: album.album_art_url.clone(),
-            url: track.url.clone(),
-        })
+        Some(f(&album, track))
     };
+    current().ok_or(DiscoveryError::NoCurrentTrack)
+}
 
-    if track.is_none() {
-        error!("Failed to get entry {}", track_i);
+fn update_data_cache(f: impl FnOnce(&mut TrackCache)) -> Result<(), DiscoveryError> {
+    let mut tc = DATA_CACHE
+        .lock()
+        .map_err(|_| DiscoveryError::Cache("failed to lock data cache".to_string()))?;
+    f(&mut tc);
+    tc.save()
+        .map_err(|error| DiscoveryError::Cache(error.to_string()))
+}
+
+pub fn mark_current_track() -> Result<(), DiscoveryError> {
+    with_current_track(add_listened)?
+}
+
+/// Skipped tracks count as listened, so they are not queued again, but are kept apart from
+/// ones played to the end
+pub fn skip_current_track() -> Result<(), DiscoveryError> {
+    skip_track_at(TRACK_CURSOR.load(Relaxed))
+}
+
+/// As [`skip_current_track`], for the track at a position in the [`playlist`]
+pub fn skip_track_at(track_i: usize) -> Result<(), DiscoveryError> {
+    let id = with_track_at(track_i, |album, track| {
+        add_listened(album, track).map(|_| track.id)
+    })??;
+    update_data_cache(|tc| {
+        tc.skipped_ids.insert(id);
+    })
+}
+
+/// Failed tracks are not queued again, without counting as listened
+pub fn fail_current_track() -> Result<(), DiscoveryError> {
+    let id = with_current_track(|_, track| track.id)?;
+    update_data_cache(|tc| {
+        tc.failed_ids.insert(id);
+    })
+}
+
+pub fn like_current_track() -> Result<(), DiscoveryError> {
+    let id = with_current_track(|_, track| track.id)?;
+    update_data_cache(|tc| {
+        tc.disliked_ids.remove(&id);
+        tc.liked_ids.insert(id);
+    })
+}
+
+/// Dislikes are marked as listened so they are never queued again
+pub fn dislike_current_track() -> Result<(), DiscoveryError> {
+    let id = with_current_track(|album, track| add_listened(album, track).map(|_| track.id))??;
+    update_data_cache(|tc| {
+        tc.liked_ids.remove(&id);
+        tc.disliked_ids.insert(id);
+    })
+}
+
+/// Forget everything recorded about the current track and its album
+pub fn unmark_current_track() -> Result<(), DiscoveryError> {
+    let (album_id, id) = with_current_track(|album, track| (album.id, track.id))?;
+    if let Some(mut listens) = ALBUM_LISTENS.get_mut(&album_id) {
+        listens.remove(&id);
     }
-    track
+    update_data_cache(|tc| {
+        tc.track_ids.remove(&id);
+        tc.album_ids.remove(&album_id);
+        tc.liked_ids.remove(&id);
+        tc.disliked_ids.remove(&id);
+        tc.skipped_ids.remove(&id);
+        tc.failed_ids.remove(&id);
+    })
+}
+
+pub fn liked(id: TrackID) -> bool {
+    DATA_CACHE
+        .lock()
+        .map(|tc| tc.liked_ids.contains(&id))
+        .unwrap_or_default()
+}
+
+pub fn liked_ids() -> Vec<TrackID> {
+    DATA_CACHE
+        .lock()
+        .map(|tc| tc.liked_ids.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+/// Tracks queued after the current one, without waiting for more to load
+pub fn upcoming(count: usize) -> Vec<Entry> {
+    let cursor = TRACK_CURSOR.load(Relaxed);
+    let cap = FILTERED_TRACK_INDEX_CAP.load(Relaxed);
+    (cursor + 1..=cap.min(cursor + count))
+        .filter_map(entry_at)
+        .collect()
+}
+
+/// Every track loaded so far, in play order, including ones already played
+pub fn playlist() -> Vec<Entry> {
+    let cap = FILTERED_TRACK_INDEX_CAP.load(Relaxed);
+    (0..=cap).filter_map(entry_at).collect()
+}
+
+/// Number of tracks in the [`playlist`]
+pub fn playlist_length() -> usize {
+    match FILTERED_TRACK_INDEX.get(0) {
+        Some(_) => FILTERED_TRACK_INDEX_CAP.load(Relaxed) + 1,
+        None => 0,
+    }
+}
+
+/// Index of the current track in the [`playlist`]
+pub fn position() -> usize {
+    TRACK_CURSOR.load(Relaxed)
 }
 
-pub fn mark_current_track() -> Option<()> {
-    let track_i = TRACK_CURSOR.load(Relaxed);
+pub fn repeat() -> Repeat {
+    REPEAT.lock().map(|repeat| *repeat).unwrap_or_default()
+}
+
+pub fn set_repeat(repeat: Repeat) {
+    match REPEAT.lock() {
+        Ok(mut current) => *current = repeat,
+        Err(_) => warn!("Failed to lock repeat"),
+    }
+    info!("Repeat: {}", repeat);
+}
+
+fn album_at(track_i: usize) -> Option<AlbumID> {
     let track_fi = *FILTERED_TRACK_INDEX.get(track_i)?;
-    let ids = MASTER_TRACK_LIST.get(track_fi)?;
-    let album = ALBUM_MAP.get(&ids.0)?;
-    let track = album.tracks.get(&ids.1)?;
+    MASTER_TRACK_LIST.get(track_fi).map(|ids| ids.0)
+}
+
+/// Positions in the [`playlist`] of the current album's tracks, which shuffling may have
+/// spread apart
+fn current_album_positions() -> Vec<usize> {
+    let cursor = TRACK_CURSOR.load(Relaxed);
+    let Some(album) = album_at(cursor) else {
+        return Vec::new();
+    };
+    let cap = FILTERED_TRACK_INDEX_CAP.load(Relaxed);
+    (0..=cap).filter(|i| album_at(*i) == Some(album)).collect()
+}
+
+/// Position of the current album's first track in the [`playlist`], to play it again from
+/// the start
+pub fn album_start() -> Option<usize> {
+    current_album_positions().first().copied()
+}
 
-    add_listened_track(track);
+/// How albums left by skipping them are marked when not told
+pub fn skip_album_mark() -> Option<AlbumMark> {
+    SKIP_ALBUM_MARK.lock().ok().and_then(|mark| *mark)
+}
+
+pub fn set_skip_album_mark(mark: Option<AlbumMark>) {
+    match SKIP_ALBUM_MARK.lock() {
+        Ok(mut current) => *current = mark,
+        Err(_) => warn!("Failed to lock skip album mark"),
+    }
+}
+
+/// Mark every track of the album of the track at a position in the [`playlist`], queued
+/// or not
+pub fn mark_album_at(track_i: usize, mark: AlbumMark) -> Result<(), DiscoveryError> {
+    let (album_id, ids) = with_track_at(track_i, |album, _| {
+        (album.id, album.tracks.keys().copied().collect::<Vec<_>>())
+    })?;
+    if let Some(mut listens) = ALBUM_LISTENS.get_mut(&album_id) {
+        listens.extend(&ids);
+    }
+    update_data_cache(|tc| {
+        tc.track_ids.extend(&ids);
+        tc.album_ids.insert(album_id);
+        if mark == AlbumMark::Disliked {
+            for id in &ids {
+                tc.liked_ids.remove(id);
+            }
+            tc.disliked_ids.extend(&ids);
+        }
+    })
+}
+
+/// Position in the [`playlist`] of the first track after the current one from another album
+pub fn skip_album() -> Result<usize, DiscoveryError> {
+    let cursor = TRACK_CURSOR.load(Relaxed);
+    let album = album_at(cursor).ok_or(DiscoveryError::NoCurrentTrack)?;
+    let cap = FILTERED_TRACK_INDEX_CAP.load(Relaxed);
+    (cursor + 1..=cap)
+        .find(|i| album_at(*i).is_some_and(|other| other != album))
+        .ok_or(DiscoveryError::NoNextAlbum)
+}
+
+/// Position in the [`playlist`] of the first track of the run of tracks from one album
+/// before the current album's
+pub fn previous_album() -> Result<usize, DiscoveryError> {
+    let run_start = |mut i: usize| {
+        let album = album_at(i);
+        while i > 0 && album_at(i - 1) == album {
+            i -= 1;
+        }
+        i
+    };
+    let start = run_start(TRACK_CURSOR.load(Relaxed));
+    if start == 0 {
+        return Err(DiscoveryError::NoPreviousAlbum);
+    }
+    Ok(run_start(start - 1))
+}
 
-    if album_listened(&album) {
-        add_listened_album(&album);
+/// Move on from a track that played to its end, as the [`Repeat`] mode has it
+pub fn next_after_finished() -> Option<Entry> {
+    match repeat() {
+        Repeat::Off => next(),
+        Repeat::One => current(),
+        Repeat::Album => {
+            let cursor = TRACK_CURSOR.load(Relaxed);
+            let positions = current_album_positions();
+            match positions
+                .iter()
+                .find(|i| **i > cursor)
+                .or(positions.first())
+            {
+                Some(i) => jump(*i),
+                None => next(),
+            }
+        }
     }
-    Some(())
 }
 
-// TODO: unmark_current_track
+/// Move the cursor onto an already loaded track
+pub fn jump(track_i: usize) -> Option<Entry> {
+    if track_i > FILTERED_TRACK_INDEX_CAP.load(Relaxed) {
+        return None;
+    }
+    let track = entry_at(track_i)?;
+    TRACK_CURSOR.store(track_i, Relaxed);
+    debug!("Cursor now at : {}", track_i);
+    Some(track)
+}
 
 pub fn current() -> Option<Entry> {
     let track = TRACK_CURSOR.load(Relaxed);
@@ -428,3 +1119,90 @@ pub fn stop() {
         }
     }
 }
+
+#[test]
+fn test_discovered_albums() {
+    use crate::bandcamp::{
+        mock::{fixture, MockResponse, MockServer},
+        models::Strategy,
+    };
+
+    let server = MockServer::start([
+        (
+            "/api/discover/3/get_web",
+            vec![MockResponse::ok(fixture("discover.json"))],
+        ),
+        (
+            "/halcyondrift/album/night-shapes",
+            vec![MockResponse::ok(fixture("album.html"))],
+        ),
+        (
+            "/paperlanterns/album/quiet-rooms",
+            vec![MockResponse::ok(fixture("no_metadata.html"))],
+        ),
+    ]);
+    let site = server.site();
+    let query = site
+        .discover_api()
+        .build_query(&Function::get_web(0, None, None, None, None))
+        .unwrap();
+    let albums = discovered_albums(&bandcamp::api::Api::request(query).unwrap(), &site);
+    let urls: Vec<&str> = albums.iter().map(|album| album.url.as_str()).collect();
+    assert_eq!(
+        urls,
+        [
+            server.url("/halcyondrift/album/night-shapes"),
+            server.url("/paperlanterns/album/quiet-rooms")
+        ]
+    );
+
+    // Playable from the feed alone
+    let album = &albums[0];
+    assert!(!album.complete());
+    assert_eq!(album.tags.as_deref(), Some("electronic"));
+    assert_eq!(
+        album.album_art_url.as_deref(),
+        Some("https://f4.bcbits.com/img/a123456789_10.jpg")
+    );
+    assert_eq!(album.featured_track_num, Some(2));
+    let featured = &album.tracks[&1002];
+    assert!(featured.valid());
+    assert_eq!(featured.duration, Some(251.5));
+    assert!(!albums[1].tracks.values().any(Track::valid));
+    let excluding = |tags: &[&str]| DiscoverySettings {
+        exclude_tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        ..Default::default()
+    };
+    assert!(excluded_album(album, &excluding(&["Electronic"])));
+    assert!(!excluded_album(album, &excluding(&["electro"])));
+
+    let album = scrape_album(albums[0].clone()).expect("Failed to scrape Album");
+    assert!(album.complete());
+    let names = |mode: PlayMode| -> Vec<String> {
+        selected_tracks(&album, mode)
+            .iter()
+            .map(|track| track.name.clone())
+            .collect()
+    };
+    assert_eq!(names(PlayMode::Album), ["Glass & Static", "Low Tide"]);
+    assert_eq!(names(PlayMode::Featured), ["Low Tide"]);
+    assert_eq!(names("sampler:1".parse().unwrap()), ["Glass & Static"]);
+    assert_eq!(
+        "featured-first".parse::<PlayMode>(),
+        Ok(PlayMode::FeaturedFirst)
+    );
+    assert!("sampler:0".parse::<PlayMode>().is_err());
+    assert_eq!(album.tracks.len(), 3);
+    assert_eq!(
+        album.strategies,
+        [
+            Strategy::LdJson,
+            Strategy::DataTralbum,
+            Strategy::DiscoverApi
+        ]
+    );
+    assert!(matches!(
+        scrape_album(albums[1].clone()),
+        Err(bandcamp::Error::ScrapeMissingField(_))
+    ));
+}
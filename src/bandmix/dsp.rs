@@ -0,0 +1,463 @@
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use rodio::{source::SeekError, Source};
+use tracing::warn;
+
+/// Center frequencies of the graphic equalizer bands
+pub const EQ_BANDS: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+const EQ_Q: f32 = 1.41;
+const LIMITER_THRESHOLD: f32 = 0.89; // ~ -1 dBFS
+const LIMITER_RELEASE_MS: f32 = 100.0;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Default, strum::EnumString, strum::Display, strum::EnumIter,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Preset {
+    #[default]
+    Flat,
+    BassBoost,
+    TrebleBoost,
+    Loudness,
+    SmallSpeakers,
+    Vocal,
+}
+
+impl Preset {
+    /// Gain in dB for each of [`EQ_BANDS`]
+    pub fn gains(&self) -> [f32; 10] {
+        match self {
+            Preset::Flat => [0.0; 10],
+            Preset::BassBoost => [6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            Preset::TrebleBoost => [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 3.0, 5.0, 6.0],
+            Preset::Loudness => [5.0, 4.0, 2.0, 0.0, -1.0, 0.0, 0.0, 1.0, 3.0, 4.0],
+            Preset::SmallSpeakers => [0.0, 6.0, 5.0, 3.0, 1.0, 0.0, 0.0, 1.0, 2.0, 2.0],
+            Preset::Vocal => [-2.0, -2.0, -1.0, 0.0, 2.0, 3.0, 3.0, 2.0, 0.0, -1.0],
+        }
+    }
+
+    pub fn next(&self) -> Preset {
+        use strum::IntoEnumIterator;
+        let mut presets = Preset::iter().cycle();
+        presets.find(|p| p == self);
+        presets.next().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DspSettings {
+    pub preset: Preset,
+    /// Per band gain in dB, starts as the preset gains but can be tuned individually
+    pub gains: [f32; 10],
+    pub preamp_db: f32,
+    pub limiter: bool,
+    pub mono: bool,
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        DspSettings {
+            preset: Preset::default(),
+            gains: Preset::default().gains(),
+            preamp_db: 0.0,
+            limiter: true,
+            mono: false,
+        }
+    }
+}
+
+impl std::fmt::Display for DspSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "EQ: {} | Limiter: {} | Mono: {}",
+            self.preset,
+            if self.limiter { "on" } else { "off" },
+            if self.mono { "on" } else { "off" }
+        ))
+    }
+}
+
+/// Shared handle used to change the effects of every [`Dsp`] source while it plays.
+#[derive(Clone, Default)]
+pub struct DspControl {
+    settings: Arc<Mutex<DspSettings>>,
+    version: Arc<AtomicUsize>,
+}
+
+impl DspControl {
+    pub fn new(settings: DspSettings) -> Self {
+        DspControl {
+            settings: Arc::new(Mutex::new(settings)),
+            version: Default::default(),
+        }
+    }
+
+    pub fn settings(&self) -> DspSettings {
+        match self.settings.lock() {
+            Ok(settings) => settings.clone(),
+            Err(_) => {
+                warn!("Failed to lock dsp settings");
+                DspSettings::default()
+            }
+        }
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut DspSettings)) {
+        if let Ok(mut settings) = self.settings.lock() {
+            f(&mut settings);
+            self.version.fetch_add(1, Relaxed);
+        } else {
+            warn!("Failed to lock dsp settings");
+        }
+    }
+
+    pub fn set_preset(&self, preset: Preset) {
+        self.update(|s| {
+            s.preset = preset;
+            s.gains = preset.gains();
+        });
+    }
+
+    pub fn set_band(&self, band: usize, gain_db: f32) {
+        self.update(|s| {
+            if let Some(gain) = s.gains.get_mut(band) {
+                *gain = gain_db.clamp(-12.0, 12.0);
+            }
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    freq: f32,
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    /// RBJ cookbook peaking filter
+    fn peaking(freq: f32, q: f32, gain_db: f32, sample_rate: u32) -> Biquad {
+        let a = 10f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * freq / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha / a;
+
+        Biquad {
+            freq,
+            b0: (1.0 + alpha * a) / a0,
+            b1: (-2.0 * cos_w0) / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: (-2.0 * cos_w0) / a0,
+            a2: (1.0 - alpha / a) / a0,
+            ..Default::default()
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Effects chain applied between the decoder and the sink: EQ, mono downmix and limiter.
+pub struct Dsp<S>
+where
+    S: Source<Item = f32>,
+{
+    input: S,
+    control: DspControl,
+    version: usize,
+    settings: DspSettings,
+    preamp: f32,
+    filters: Vec<Vec<Biquad>>,
+    limiter_gain: f32,
+    limiter_release: f32,
+    frame: Vec<f32>,
+    position: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl<S> Dsp<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, control: DspControl) -> Dsp<S> {
+        let channels = input.channels();
+        let sample_rate = input.sample_rate();
+        let mut dsp = Dsp {
+            input,
+            control,
+            version: 0,
+            settings: DspSettings::default(),
+            preamp: 1.0,
+            filters: Vec::new(),
+            limiter_gain: 1.0,
+            limiter_release: 0.0,
+            frame: Vec::new(),
+            position: 0,
+            channels,
+            sample_rate,
+        };
+        dsp.rebuild();
+        dsp
+    }
+
+    fn rebuild(&mut self) {
+        self.version = self.control.version.load(Relaxed);
+        self.settings = self.control.settings();
+        self.preamp = 10f32.powf(self.settings.preamp_db / 20.0);
+        self.limiter_release =
+            (-1.0 / (LIMITER_RELEASE_MS / 1000.0 * self.sample_rate.max(1) as f32)).exp();
+
+        let nyquist = self.sample_rate as f32 / 2.0;
+        let bands: Vec<Biquad> = EQ_BANDS
+            .iter()
+            .zip(self.settings.gains.iter())
+            .filter(|(freq, gain)| **gain != 0.0 && **freq < nyquist)
+            .map(|(freq, gain)| Biquad::peaking(*freq, EQ_Q, *gain, self.sample_rate))
+            .collect();
+
+        // Keep the history of bands that stay on, where only the coefficients change, to avoid
+        // clicks. Bands switched on start silent, as another band's history does not fit them.
+        let mut filters = vec![bands; self.channels as usize];
+        for (new, old) in filters.iter_mut().zip(self.filters.iter()) {
+            for n in new.iter_mut() {
+                if let Some(o) = old.iter().find(|o| o.freq == n.freq) {
+                    (n.x1, n.x2, n.y1, n.y2) = (o.x1, o.x2, o.y1, o.y2);
+                }
+            }
+        }
+        self.filters = filters;
+    }
+
+    fn load_frame(&mut self) -> Option<()> {
+        let channels = self.input.channels();
+        let sample_rate = self.input.sample_rate();
+        if channels != self.channels || sample_rate != self.sample_rate {
+            self.channels = channels;
+            self.sample_rate = sample_rate;
+            self.filters.clear();
+            self.rebuild();
+        } else if self.version != self.control.version.load(Relaxed) {
+            self.rebuild();
+        }
+
+        self.frame.clear();
+        self.position = 0;
+        for _ in 0..self.channels {
+            match self.input.next() {
+                Some(sample) => self.frame.push(sample * self.preamp),
+                None => break,
+            }
+        }
+        if self.frame.is_empty() {
+            return None;
+        }
+
+        for (sample, filters) in self.frame.iter_mut().zip(self.filters.iter_mut()) {
+            for filter in filters.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        }
+
+        if self.settings.mono && self.frame.len() > 1 {
+            let mix = self.frame.iter().sum::<f32>() / self.frame.len() as f32;
+            self.frame.fill(mix);
+        }
+
+        if self.settings.limiter {
+            let peak = self.frame.iter().fold(0f32, |p, s| p.max(s.abs()));
+            let target = if peak > LIMITER_THRESHOLD {
+                LIMITER_THRESHOLD / peak
+            } else {
+                1.0
+            };
+            if target < self.limiter_gain {
+                self.limiter_gain = target;
+            } else {
+                self.limiter_gain = target + (self.limiter_gain - target) * self.limiter_release;
+            }
+            for sample in self.frame.iter_mut() {
+                *sample = (*sample * self.limiter_gain).clamp(-1.0, 1.0);
+            }
+        }
+        Some(())
+    }
+}
+
+impl<S> Iterator for Dsp<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position >= self.frame.len() {
+            self.load_frame()?;
+        }
+        let sample = self.frame[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Dsp<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        let buffered = self.frame.len() - self.position;
+        self.input.current_frame_len().map(|len| len + buffered)
+    }
+
+    fn channels(&self) -> u16 {
+        if self.position < self.frame.len() {
+            self.channels
+        } else {
+            self.input.channels()
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        if self.position < self.frame.len() {
+            self.sample_rate
+        } else {
+            self.input.sample_rate()
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.frame.clear();
+        self.position = 0;
+        self.filters.clear();
+        self.rebuild();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_mono_downmix() {
+    let control = DspControl::new(DspSettings {
+        mono: true,
+        limiter: false,
+        ..Default::default()
+    });
+    let input = rodio::buffer::SamplesBuffer::new(2, 44100, vec![0.5f32, -0.5, 0.2, 0.4]);
+    let output: Vec<f32> = Dsp::new(input, control).collect();
+    assert_eq!(output.len(), 4);
+    assert!(output[0].abs() < 1e-6 && output[1].abs() < 1e-6);
+    assert!((output[2] - 0.3).abs() < 1e-6 && (output[3] - 0.3).abs() < 1e-6);
+}
+
+#[cfg(test)]
+fn sine(freq: f32, amplitude: f32, samples: usize) -> rodio::buffer::SamplesBuffer<f32> {
+    let samples = (0..samples)
+        .map(|i| amplitude * (2.0 * PI * freq * i as f32 / 44100.0).sin())
+        .collect::<Vec<f32>>();
+    rodio::buffer::SamplesBuffer::new(1, 44100, samples)
+}
+
+#[cfg(test)]
+fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0f32, |p, s| p.max(s.abs()))
+}
+
+#[test]
+fn test_eq() {
+    let eq = |preset: Preset, freq: f32| {
+        let control = DspControl::new(DspSettings {
+            limiter: false,
+            ..Default::default()
+        });
+        control.set_preset(preset);
+        let output: Vec<f32> = Dsp::new(sine(freq, 0.1, 44100), control).collect();
+        // Past the filters settling in
+        peak(&output[22050..]) / 0.1
+    };
+    assert!((eq(Preset::Flat, 62.0) - 1.0).abs() < 1e-3);
+    // +5 dB at the band itself, more where neighbouring bands overlap
+    assert!(eq(Preset::BassBoost, 62.0) > 1.7);
+    assert!((eq(Preset::BassBoost, 8000.0) - 1.0).abs() < 0.02);
+    assert!(eq(Preset::Vocal, 31.0) < 0.85);
+}
+
+#[test]
+fn test_rebuild_history() {
+    let control = DspControl::new(DspSettings {
+        limiter: false,
+        ..Default::default()
+    });
+    control.set_band(0, 6.0);
+    let mut dsp = Dsp::new(sine(31.0, 0.5, 4410), control.clone());
+    dsp.by_ref().take(100).for_each(drop);
+    let history = dsp.filters[0][0];
+
+    // Retuning a band keeps its history
+    control.set_band(0, 3.0);
+    dsp.next();
+    assert_eq!(dsp.filters[0][0].freq, 31.0);
+    assert_ne!(dsp.filters[0][0].x2, 0.0);
+    assert_eq!(dsp.filters[0][0].x2, history.x1);
+
+    // A band switched on does not inherit the history of the one switched off
+    control.update(|s| s.gains = [0.0, 3.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    dsp.next();
+    assert_eq!(dsp.filters[0].len(), 1);
+    assert_eq!(dsp.filters[0][0].freq, 62.0);
+    assert_eq!(dsp.filters[0][0].x2, 0.0);
+    assert_eq!(dsp.filters[0][0].y2, 0.0);
+}
+
+#[test]
+fn test_limiter() {
+    let limit = |limiter: bool| {
+        let control = DspControl::new(DspSettings {
+            limiter,
+            ..Default::default()
+        });
+        let mut samples = vec![1.5f32; 441];
+        samples.extend(vec![0.1f32; 44100]);
+        let input = rodio::buffer::SamplesBuffer::new(1, 44100, samples);
+        Dsp::new(input, control).collect::<Vec<f32>>()
+    };
+
+    let output = limit(true);
+    // Loud samples are held down at once
+    assert!(peak(&output[..441]) <= LIMITER_THRESHOLD + 1e-6);
+    // and quiet ones after them recover over the release time
+    assert!(output[441] < 0.07);
+    assert!((output[output.len() - 1] - 0.1).abs() < 1e-4);
+
+    let output = limit(false);
+    assert_eq!(output[0], 1.5);
+    assert_eq!(output[441], 0.1);
+}
@@ -0,0 +1,60 @@
+use std::{sync::Arc, time::Duration};
+
+use souvlaki::MediaControlEvent;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::debug;
+
+use super::{discovery::AlbumMark, stream::StreamError};
+use crate::bandcamp::models::TrackID;
+
+/// Everything the main loop reacts to, handled one at a time in the order they were sent
+#[derive(Debug)]
+pub enum Event {
+    /// From media keys, the terminal, or any of the control servers
+    Control(MediaControlEvent),
+    /// The user skipped the current track
+    UserSkip,
+    /// The user went back a track
+    UserPrevious,
+    /// The player reached the end of a track, ignored once another is playing
+    TrackFinished(TrackID),
+    /// A track could not be played, or stopped early, ignored once another is playing
+    TrackError { id: TrackID, error: StreamError },
+    /// The track at a position in the playlist finished loading, to be played if the cursor
+    /// is still on it
+    Loaded(usize),
+    /// Try a failed track again, from where it stopped
+    Retry { id: TrackID, position: Duration },
+    /// Move playback onto another output device, `None` being the system default
+    SetDevice(Option<String>),
+    /// Play the track at a position in the playlist, counting the current one as skipped
+    /// if `mark_skipped`
+    Jump { position: usize, mark_skipped: bool },
+    /// Leave the current album for the track at a position in the playlist, marking the
+    /// album with `mark`, or as set by `--skip-album-mark` when `None`
+    SkipAlbum {
+        position: usize,
+        mark: Option<AlbumMark>,
+    },
+}
+
+/// Queues an event, for control surfaces running on other threads.
+pub type EventSender = Arc<dyn Fn(Event) + Send + Sync>;
+
+impl From<MediaControlEvent> for Event {
+    fn from(event: MediaControlEvent) -> Event {
+        match event {
+            MediaControlEvent::Next => Event::UserSkip,
+            MediaControlEvent::Previous => Event::UserPrevious,
+            event => Event::Control(event),
+        }
+    }
+}
+
+pub fn control_sender(events: UnboundedSender<Event>) -> EventSender {
+    Arc::new(move |event: Event| {
+        if events.send(event).is_err() {
+            debug!("Event dropped, player has stopped");
+        }
+    })
+}
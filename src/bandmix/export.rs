@@ -0,0 +1,201 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Datelike, NaiveDateTime};
+use directories::UserDirs;
+use id3::{
+    frame::{Picture, PictureType},
+    Tag, TagLike, Timestamp, Version,
+};
+use reqwest::blocking::Response;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use super::{cache::AudioCache, discovery::Entry};
+use crate::bandcamp::{self, client};
+
+pub const DEFAULT_TEMPLATE: &str = "{artist}/{album}/{num} - {title}";
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("no stream url for {0}")]
+    NoStreamUrl(String),
+    #[error("failed to download: {0}")]
+    Download(#[from] bandcamp::Error),
+    #[error("failed to write: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to tag: {0}")]
+    Tag(#[from] id3::Error),
+}
+
+/// Where and how saved tracks are written
+#[derive(Clone, Debug)]
+pub struct ExportSettings {
+    pub dir: PathBuf,
+    /// Filename without extension, with `{artist}`, `{album}`, `{title}`, `{num}` and `{date}`
+    /// placeholders. `/` separates directories.
+    pub template: String,
+}
+
+impl ExportSettings {
+    pub fn default_dir() -> PathBuf {
+        UserDirs::new()
+            .and_then(|dirs| dirs.audio_dir().map(Path::to_path_buf))
+            .unwrap_or_default()
+            .join("bandmix")
+    }
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        ExportSettings {
+            dir: ExportSettings::default_dir(),
+            template: DEFAULT_TEMPLATE.to_string(),
+        }
+    }
+}
+
+fn sanitize(component: &str) -> String {
+    let clean: String = component
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let clean = clean.trim().trim_end_matches('.').to_string();
+    if clean.is_empty() {
+        "_".to_string()
+    } else {
+        clean
+    }
+}
+
+/// Bandcamp dates look like `06 Aug 2024 00:00:00 GMT`
+fn parse_release_date(date: &str) -> Option<Timestamp> {
+    let date = NaiveDateTime::parse_from_str(date.trim_end_matches(" GMT"), "%d %b %Y %H:%M:%S")
+        .ok()?
+        .date();
+    Some(Timestamp {
+        year: date.year(),
+        month: Some(date.month() as u8),
+        day: Some(date.day() as u8),
+        hour: None,
+        minute: None,
+        second: None,
+    })
+}
+
+fn file_path(settings: &ExportSettings, track: &Entry) -> PathBuf {
+    let date = parse_release_date(&track.release_date)
+        .map(|t| t.year.to_string())
+        .unwrap_or_default();
+    let mut path = settings.dir.clone();
+    let mut components = settings.template.split('/').peekable();
+    while let Some(component) = components.next() {
+        let component = component
+            .replace("{artist}", &track.artist)
+            .replace("{album}", &track.album_name)
+            .replace("{title}", &track.name)
+            .replace("{num}", &format!("{:02}", track.num))
+            .replace("{date}", &date);
+        // Appended rather than set, as titles such as "Mr. Blue Sky" have dots of their own
+        match components.peek() {
+            Some(_) => path.push(sanitize(&component)),
+            None => path.push(format!("{}.mp3", sanitize(&component))),
+        }
+    }
+    path
+}
+
+fn read_bytes(response: Response) -> bandcamp::Result<Vec<u8>> {
+    Ok(response.bytes()?.to_vec())
+}
+
+/// Write the mp3-128 stream of a track to disk, tagged with its album information.
+pub fn save(
+    track: &Entry,
+    settings: &ExportSettings,
+    cache: Option<&AudioCache>,
+) -> Result<PathBuf, ExportError> {
+    if track.url.is_empty() {
+        return Err(ExportError::NoStreamUrl(track.name.clone()));
+    }
+    let path = file_path(settings, track);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match cache.and_then(|c| c.get(track.id)) {
+        Some(cached) => {
+            debug!("Saving from cache: {}", cached.display());
+            fs::copy(cached, &path)?;
+        }
+        None => fs::write(&path, read_bytes(client::download(&track.url)?)?)?,
+    }
+
+    let mut tag = Tag::new();
+    tag.set_title(&track.name);
+    tag.set_artist(&track.artist);
+    tag.set_album_artist(&track.album_artist);
+    tag.set_album(&track.album_name);
+    if let Ok(num) = u32::try_from(track.num) {
+        tag.set_track(num);
+    }
+    if let Some(date) = parse_release_date(&track.release_date) {
+        tag.set_date_released(date);
+        tag.set_year(date.year);
+    }
+    if let Some(url) = &track.album_art_url {
+        match client::get(url).and_then(read_bytes) {
+            Ok(data) => tag.add_frame(Picture {
+                mime_type: "image/jpeg".to_string(),
+                picture_type: PictureType::CoverFront,
+                description: String::new(),
+                data,
+            }),
+            Err(error) => {
+                warn!("Failed to get cover art : {}", error);
+                None
+            }
+        };
+    }
+    tag.write_to_path(&path, Version::Id3v24)?;
+
+    Ok(path)
+}
+
+#[test]
+fn test_file_path() {
+    let settings = ExportSettings {
+        dir: PathBuf::from("out"),
+        template: DEFAULT_TEMPLATE.to_string(),
+    };
+    let track = Entry {
+        name: "What/Now?".to_string(),
+        artist: "Artist".to_string(),
+        album_name: "Album".to_string(),
+        num: 3,
+        ..Default::default()
+    };
+    assert_eq!(
+        file_path(&settings, &track),
+        PathBuf::from("out/Artist/Album/03 - What_Now_.mp3")
+    );
+    let track = Entry {
+        name: "Mr. Blue Sky".to_string(),
+        album_name: "Vol. 2".to_string(),
+        ..track
+    };
+    assert_eq!(
+        file_path(&settings, &track),
+        PathBuf::from("out/Artist/Vol. 2/03 - Mr. Blue Sky.mp3")
+    );
+    assert_eq!(
+        parse_release_date("06 Aug 2024 00:00:00 GMT").map(|t| (t.year, t.month, t.day)),
+        Some((2024, Some(8), Some(6)))
+    );
+}
@@ -0,0 +1,257 @@
+//! Re-streams the mp3 being played to HTTP listeners, the way an Icecast mount does, with ICY
+//! metadata for clients that ask for it. Bytes are forwarded as the decoder reads them, so
+//! listeners follow local playback, pauses included, without re-encoding.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:8000";
+/// Audio bytes between ICY metadata blocks
+const META_INTERVAL: usize = 16_000;
+/// Chunks a listener may fall behind before audio is dropped for it
+const LISTENER_BACKLOG: usize = 64;
+
+type Chunk = Arc<[u8]>;
+
+/// Shared between the player, which feeds it, and the listener connections.
+#[derive(Default)]
+pub struct Broadcast {
+    listeners: Mutex<Vec<SyncSender<Chunk>>>,
+    title: Mutex<String>,
+    /// Bumped for every new track so readers of the previous one stop forwarding
+    generation: AtomicUsize,
+}
+
+impl Broadcast {
+    /// Start forwarding a new track, returning a reader that feeds the broadcast.
+    pub fn track<R: Read + Seek>(
+        self: &Arc<Self>,
+        title: String,
+        mut reader: R,
+    ) -> BroadcastReader<R> {
+        if let Ok(mut current) = self.title.lock() {
+            *current = title;
+        }
+        let mut header = [0u8; 10];
+        let skip = match reader.read_exact(&mut header) {
+            Ok(()) => id3_size(&header).unwrap_or_default(),
+            Err(_) => 0,
+        };
+        if let Err(error) = reader.seek(SeekFrom::Start(0)) {
+            warn!("Failed to rewind track for streaming : {}", error);
+        }
+        BroadcastReader {
+            reader,
+            broadcast: Arc::clone(self),
+            generation: self.generation.fetch_add(1, Relaxed) + 1,
+            position: 0,
+            forwarded: 0,
+            skip,
+        }
+    }
+
+    fn send(&self, data: &[u8]) {
+        let Ok(mut listeners) = self.listeners.lock() else {
+            return;
+        };
+        if listeners.is_empty() {
+            return;
+        }
+        let chunk: Chunk = data.into();
+        listeners.retain(|listener| match listener.try_send(Arc::clone(&chunk)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                debug!("Listener fell behind, dropping audio");
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    fn subscribe(&self) -> Receiver<Chunk> {
+        let (tx, rx) = mpsc::sync_channel(LISTENER_BACKLOG);
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.push(tx);
+        }
+        rx
+    }
+
+    fn title(&self) -> String {
+        self.title.lock().map(|t| t.clone()).unwrap_or_default()
+    }
+}
+
+/// Passes reads through while forwarding each byte once, skipping the ID3 tag which has no
+/// place in the middle of a stream.
+pub struct BroadcastReader<R> {
+    reader: R,
+    broadcast: Arc<Broadcast>,
+    generation: usize,
+    position: u64,
+    forwarded: u64,
+    skip: u64,
+}
+
+/// Size of the ID3v2 tag at the start of `data`, if there is one
+fn id3_size(data: &[u8; 10]) -> Option<u64> {
+    if &data[..3] != b"ID3" {
+        return None;
+    }
+    let size = data[6..10]
+        .iter()
+        .fold(0u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+impl<R: Read> Read for BroadcastReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        let data = &buf[..read];
+        let start = self.position;
+        self.position += read as u64;
+        if self.broadcast.generation.load(Relaxed) != self.generation {
+            return Ok(read);
+        }
+        let from = self.forwarded.max(self.skip).max(start);
+        if from < self.position {
+            self.broadcast.send(&data[(from - start) as usize..]);
+            self.forwarded = self.position;
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for BroadcastReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.position = self.reader.seek(position)?;
+        Ok(self.position)
+    }
+}
+
+/// An ICY metadata block: a length byte counting 16 byte units, then the padded text
+fn metadata(title: &str) -> Vec<u8> {
+    let text = format!("StreamTitle='{}';", title.replace('\'', "’"));
+    let blocks = text.len().div_ceil(16).min(255);
+    let mut block = vec![blocks as u8];
+    block.extend(text.bytes().take(blocks * 16));
+    block.resize(1 + blocks * 16, 0);
+    block
+}
+
+fn serve(stream: TcpStream, broadcast: Arc<Broadcast>) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut writer = stream.try_clone()?;
+    let mut icy = false;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("icy-metadata") && value.trim() == "1" {
+                icy = true;
+            }
+        }
+    }
+
+    let mut headers = "HTTP/1.0 200 OK\r\n\
+                       Content-Type: audio/mpeg\r\n\
+                       Cache-Control: no-cache\r\n\
+                       icy-name: bandmix\r\n\
+                       icy-description: Bandcamp discover radio\r\n\
+                       icy-br: 128\r\n"
+        .to_string();
+    if icy {
+        headers += &format!("icy-metaint: {}\r\n", META_INTERVAL);
+    }
+    headers += "\r\n";
+    writer.write_all(headers.as_bytes())?;
+    info!("Stream listener connected : {}", peer);
+
+    let chunks = broadcast.subscribe();
+    let mut until_meta = META_INTERVAL;
+    let mut last_title = None;
+    for chunk in chunks {
+        let mut chunk: &[u8] = &chunk;
+        if !icy {
+            writer.write_all(chunk)?;
+            continue;
+        }
+        while !chunk.is_empty() {
+            let part = chunk.len().min(until_meta);
+            writer.write_all(&chunk[..part])?;
+            chunk = &chunk[part..];
+            until_meta -= part;
+            if until_meta == 0 {
+                // Titles are only sent when they change, an empty block keeps the current one
+                let title = broadcast.title();
+                if last_title.as_ref() != Some(&title) {
+                    writer.write_all(&metadata(&title))?;
+                    last_title = Some(title);
+                } else {
+                    writer.write_all(&[0])?;
+                }
+                until_meta = META_INTERVAL;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Listen for stream listeners on `address`, each served on its own thread.
+pub fn start(address: impl ToSocketAddrs) -> io::Result<Arc<Broadcast>> {
+    let listener = TcpListener::bind(address)?;
+    info!("Streaming on http://{}", listener.local_addr()?);
+    let broadcast = Arc::new(Broadcast::default());
+    let broadcast_clone = Arc::clone(&broadcast);
+    thread::Builder::new()
+        .name("Stream server".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let broadcast = Arc::clone(&broadcast_clone);
+                        let _ = thread::Builder::new()
+                            .name("Stream listener".to_string())
+                            .spawn(move || {
+                                if let Err(error) = serve(stream, broadcast) {
+                                    debug!("Stream listener left : {}", error);
+                                }
+                            });
+                    }
+                    Err(error) => warn!("Stream connection failed : {}", error),
+                }
+            }
+        })?;
+    Ok(broadcast)
+}
+
+#[test]
+fn test_broadcast_reader() {
+    let broadcast = Arc::new(Broadcast::default());
+    let chunks = broadcast.subscribe();
+
+    let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x02..".to_vec();
+    data.extend(b"audio");
+    let mut reader = broadcast.track("A - B".to_string(), io::Cursor::new(data));
+    let mut buf = [0u8; 8];
+    while reader.read(&mut buf).unwrap() > 0 {}
+    // Re-reading after a seek forwards nothing twice
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    while reader.read(&mut buf).unwrap() > 0 {}
+
+    let sent: Vec<u8> = chunks.try_iter().flat_map(|c| c.to_vec()).collect();
+    assert_eq!(sent, b"audio");
+    assert_eq!(metadata("A - B").len(), 1 + 32);
+}
@@ -0,0 +1,25 @@
+//! Stand-ins for the main loop, so control surfaces can be tested without a player.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::events::EventSender;
+
+/// Held by tests that read or change process-wide player state, such as the play mode,
+/// repeat, status or playlist, so they do not run at the same time.
+static PLAYER_STATE: Mutex<()> = Mutex::new(());
+
+pub fn lock_player_state() -> MutexGuard<'static, ()> {
+    PLAYER_STATE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// An [`EventSender`] recording the `Debug` form of every event sent through it
+pub fn recorder() -> (EventSender, Arc<Mutex<Vec<String>>>) {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&sent);
+    let send_event: EventSender = Arc::new(move |event| {
+        recorded.lock().unwrap().push(format!("{:?}", event));
+    });
+    (send_event, sent)
+}
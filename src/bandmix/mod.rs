@@ -1,3 +1,16 @@
+pub mod cache;
 pub mod controls;
+pub mod daemon;
 pub mod discovery;
+pub mod dsp;
+pub mod events;
+pub mod export;
+pub mod icecast;
+#[cfg(test)]
+pub mod mock;
+pub mod mpd;
+pub mod shuffle;
+pub mod status;
 pub mod stream;
+pub mod tui;
+pub mod web;
@@ -0,0 +1,578 @@
+//! A subset of the [MPD protocol](https://mpd.readthedocs.io/en/latest/protocol.html), enough
+//! for common clients to show and control bandmix. The playlist is every track discovered so
+//! far, with the discovery cursor as the current song.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use souvlaki::MediaControlEvent;
+use tracing::{debug, info, warn};
+
+use super::{
+    discovery::{self, Entry, Repeat},
+    events::{Event, EventSender},
+    status::{self, Status},
+};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:6600";
+const VERSION: &str = "0.23.5";
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+const ACK_ARG: u32 = 2;
+const ACK_UNKNOWN: u32 = 5;
+const ACK_NO_EXIST: u32 = 50;
+
+const COMMANDS: &[&str] = &[
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "currentsong",
+    "decoders",
+    "getvol",
+    "idle",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "repeat",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "urlhandlers",
+    "volume",
+];
+const TAG_TYPES: &[&str] = &["Artist", "AlbumArtist", "Album", "Title", "Track", "Genre"];
+
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    fn new(code: u32, message: impl Into<String>) -> Ack {
+        Ack {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The error line for the `index`th command of a list, or 0 outside of one
+    fn line(&self, index: usize, command: &str) -> String {
+        format!(
+            "ACK [{}@{}] {{{}}} {}",
+            self.code, index, command, self.message
+        )
+    }
+}
+
+/// What `idle` watches for changes
+#[derive(PartialEq, Clone)]
+struct Snapshot {
+    song: usize,
+    track: Entry,
+    paused: bool,
+    volume: u32,
+    playlist_length: usize,
+    repeat: Repeat,
+}
+
+impl Snapshot {
+    fn take() -> Snapshot {
+        let status = status::get();
+        Snapshot {
+            song: discovery::position(),
+            paused: status.paused,
+            volume: volume(&status),
+            track: status.track,
+            playlist_length: discovery::playlist_length(),
+            repeat: discovery::repeat(),
+        }
+    }
+
+    fn changes(&self, other: &Snapshot) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        if self.song != other.song || self.track != other.track || self.paused != other.paused {
+            changes.push("player");
+        }
+        if self.volume != other.volume {
+            changes.push("mixer");
+        }
+        if self.playlist_length != other.playlist_length {
+            changes.push("playlist");
+        }
+        if self.repeat != other.repeat {
+            changes.push("options");
+        }
+        changes
+    }
+}
+
+fn volume(status: &Status) -> u32 {
+    (status.volume * 100.0).round() as u32
+}
+
+/// Split a command line into words, honouring double quotes and backslash escapes
+fn split(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => word.extend(chars.next()),
+                    c => word.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+        }
+        words.push(word);
+    }
+    words
+}
+
+fn song_info(response: &mut Vec<String>, position: usize, track: &Entry, status: &Status) {
+    response.push(format!("file: {}", track.url));
+    response.push(format!("Title: {}", track.name));
+    response.push(format!("Artist: {}", track.artist));
+    response.push(format!("AlbumArtist: {}", track.album_artist));
+    response.push(format!("Album: {}", track.album_name));
+    response.push(format!("Track: {}", track.num));
+    if let Some(tags) = &track.tags {
+        response.push(format!("Genre: {}", tags));
+    }
+    if let Some(label) = &track.label {
+        response.push(format!("Label: {}", label));
+    }
+    let playing = status.duration.filter(|_| track.id == status.track.id);
+    if let Some(duration) = playing.map(|d| d.as_secs_f64()).or(track.duration) {
+        response.push(format!("Time: {}", duration as u64));
+        response.push(format!("duration: {:.3}", duration));
+    }
+    response.push(format!("Pos: {}", position));
+    response.push(format!("Id: {}", position));
+}
+
+fn parse<T: std::str::FromStr>(argument: Option<&String>) -> Result<T, Ack> {
+    let argument = argument.ok_or_else(|| Ack::new(ACK_ARG, "missing argument"))?;
+    argument
+        .parse()
+        .map_err(|_| Ack::new(ACK_ARG, format!("Invalid argument: {}", argument)))
+}
+
+fn play(position: usize, send_event: &EventSender) -> Result<(), Ack> {
+    if position >= discovery::playlist_length() {
+        return Err(Ack::new(ACK_NO_EXIST, "No such song"));
+    }
+    if position == discovery::position() {
+        send_event(MediaControlEvent::Play.into());
+        return Ok(());
+    }
+    send_event(Event::Jump {
+        position,
+        mark_skipped: true,
+    });
+    Ok(())
+}
+
+/// The repeat mode after MPD's `repeat` or `single` is switched on or off. MPD's repeat goes
+/// round the playlist, here the album, and single repeats the song.
+fn switch_repeat(current: Repeat, command: &str, on: bool) -> Repeat {
+    match (command, on, current) {
+        ("repeat", false, _) => Repeat::Off,
+        ("repeat", true, Repeat::Off) => Repeat::Album,
+        ("single", false, Repeat::One) => Repeat::Off,
+        ("single", true, _) => Repeat::One,
+        _ => current,
+    }
+}
+
+/// Run a single command, returning its response lines
+fn handle(words: &[String], send_event: &EventSender) -> Result<Vec<String>, Ack> {
+    let Some(command) = words.first() else {
+        return Err(Ack::new(ACK_UNKNOWN, "No command given"));
+    };
+    let argument = words.get(1);
+    let status = status::get();
+    let mut response = Vec::new();
+    match command.as_str() {
+        "ping" | "clearerror" | "password" | "binarylimit" => {}
+        "play" | "playid" => match argument {
+            Some(_) => play(parse(argument)?, send_event)?,
+            None => send_event(MediaControlEvent::Play.into()),
+        },
+        "pause" => match argument.map(String::as_str) {
+            Some("1") => send_event(MediaControlEvent::Pause.into()),
+            Some("0") => send_event(MediaControlEvent::Play.into()),
+            _ => send_event(MediaControlEvent::Toggle.into()),
+        },
+        // Stopping quits bandmix, so clients only get to pause it
+        "stop" => send_event(MediaControlEvent::Pause.into()),
+        "next" => send_event(MediaControlEvent::Next.into()),
+        "previous" => send_event(MediaControlEvent::Previous.into()),
+        "setvol" => {
+            let volume: u32 = parse(argument)?;
+            send_event(MediaControlEvent::SetVolume(volume.min(100) as f64 / 100.0).into());
+        }
+        "volume" => {
+            let change: i32 = parse(argument)?;
+            let volume = (volume(&status) as i32 + change).clamp(0, 100);
+            send_event(MediaControlEvent::SetVolume(volume as f64 / 100.0).into());
+        }
+        "repeat" | "single" => {
+            let current = discovery::repeat();
+            let repeat = switch_repeat(current, command, parse::<u8>(argument)? != 0);
+            if repeat != current {
+                discovery::set_repeat(repeat);
+            }
+        }
+        "getvol" => response.push(format!("volume: {}", volume(&status))),
+        "status" => {
+            let song = discovery::position();
+            let playlist_length = discovery::playlist_length();
+            let state = status.state();
+            response.extend([
+                format!("volume: {}", volume(&status)),
+                format!("repeat: {}", u8::from(discovery::repeat() != Repeat::Off)),
+                "random: 0".to_string(),
+                format!("single: {}", u8::from(discovery::repeat() == Repeat::One)),
+                "consume: 0".to_string(),
+                format!("playlist: {}", playlist_length),
+                format!("playlistlength: {}", playlist_length),
+                format!("state: {}", state),
+            ]);
+            if state != "stop" {
+                response.push(format!("song: {}", song));
+                response.push(format!("songid: {}", song));
+                let elapsed = status.position.as_secs_f64();
+                let duration = status.duration.unwrap_or_default().as_secs_f64();
+                response.push(format!("time: {:.0}:{:.0}", elapsed, duration));
+                response.push(format!("elapsed: {:.3}", elapsed));
+                response.push(format!("duration: {:.3}", duration));
+                response.push("bitrate: 128".to_string());
+            }
+            if song + 1 < playlist_length {
+                response.push(format!("nextsong: {}", song + 1));
+                response.push(format!("nextsongid: {}", song + 1));
+            }
+        }
+        "currentsong" => {
+            if status.state() != "stop" {
+                song_info(&mut response, discovery::position(), &status.track, &status);
+            }
+        }
+        "playlistinfo" | "playlistid" | "plchanges" | "plchangesposid" => {
+            // Songs are only ever appended, so the playlist version is its length
+            let from = match command.as_str() {
+                "plchanges" | "plchangesposid" => parse(argument)?,
+                _ => 0,
+            };
+            let only = match command.as_str() {
+                "playlistinfo" | "playlistid" if argument.is_some() => {
+                    Some(parse::<usize>(argument)?)
+                }
+                _ => None,
+            };
+            for (position, track) in discovery::playlist().iter().enumerate().skip(from) {
+                if only.is_some_and(|only| only != position) {
+                    continue;
+                }
+                if command == "plchangesposid" {
+                    response.push(format!("cpos: {}", position));
+                    response.push(format!("Id: {}", position));
+                } else {
+                    song_info(&mut response, position, track, &status);
+                }
+            }
+        }
+        "outputs" => response.extend([
+            "outputid: 0".to_string(),
+            format!("outputname: {}", status.device),
+            "plugin: rodio".to_string(),
+            "outputenabled: 1".to_string(),
+        ]),
+        "stats" => response.extend([
+            format!("songs: {}", discovery::playlist_length()),
+            "uptime: 0".to_string(),
+            "playtime: 0".to_string(),
+        ]),
+        "commands" => response.extend(COMMANDS.iter().map(|c| format!("command: {}", c))),
+        "tagtypes" => response.extend(TAG_TYPES.iter().map(|t| format!("tagtype: {}", t))),
+        "urlhandlers" => response.push("handler: https://".to_string()),
+        "notcommands" | "decoders" | "listplaylists" | "lsinfo" | "list" | "find" | "search"
+        | "channels" | "readmessages" | "subscribe" | "unsubscribe" => {}
+        _ => {
+            return Err(Ack::new(
+                ACK_UNKNOWN,
+                format!("unknown command \"{}\"", command),
+            ))
+        }
+    }
+    Ok(response)
+}
+
+/// Wait for changes until one happens or the client sends `noidle`
+fn idle(lines: &Receiver<String>, subsystems: &[String]) -> Option<Vec<String>> {
+    let before = Snapshot::take();
+    loop {
+        match lines.recv_timeout(IDLE_POLL) {
+            Ok(line) if line.trim() == "noidle" => return Some(Vec::new()),
+            Ok(line) => {
+                debug!("MPD command during idle ignored : {}", line);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+        let changes: Vec<String> = Snapshot::take()
+            .changes(&before)
+            .into_iter()
+            .filter(|c| subsystems.is_empty() || subsystems.iter().any(|s| s == c))
+            .map(|c| format!("changed: {}", c))
+            .collect();
+        if !changes.is_empty() {
+            return Some(changes);
+        }
+    }
+}
+
+fn serve(stream: TcpStream, send_event: EventSender) {
+    let peer = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    debug!("MPD client connected : {}", peer);
+    let Ok(mut writer) = stream.try_clone() else {
+        warn!("Failed to clone MPD connection");
+        return;
+    };
+    let (line_tx, lines) = mpsc::channel();
+    let _ = thread::Builder::new()
+        .name("MPD reader".to_string())
+        .spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+    let mut reply = format!("OK MPD {}\n", VERSION);
+    // Commands collected between `command_list_begin` and `command_list_end`
+    let mut list: Option<(Vec<Vec<String>>, bool)> = None;
+    loop {
+        if writer.write_all(reply.as_bytes()).is_err() {
+            break;
+        }
+        reply = String::new();
+        let Ok(line) = lines.recv() else {
+            break;
+        };
+        let words = split(&line);
+        let command = words.first().map(String::as_str).unwrap_or_default();
+        match (command, &mut list) {
+            ("command_list_begin", None) => list = Some((Vec::new(), false)),
+            ("command_list_ok_begin", None) => list = Some((Vec::new(), true)),
+            ("command_list_end", Some((commands, list_ok))) => {
+                let mut failed = false;
+                for (i, words) in commands.iter().enumerate() {
+                    match handle(words, &send_event) {
+                        Ok(response) => {
+                            response.iter().for_each(|l| reply += &format!("{}\n", l));
+                            if *list_ok {
+                                reply += "list_OK\n";
+                            }
+                        }
+                        Err(ack) => {
+                            reply += &format!("{}\n", ack.line(i, &words[0]));
+                            failed = true;
+                            break;
+                        }
+                    }
+                }
+                if !failed {
+                    reply += "OK\n";
+                }
+                list = None;
+            }
+            (_, Some((commands, _))) => commands.push(words),
+            ("close", None) => break,
+            ("idle", None) => match idle(&lines, &words[1..]) {
+                Some(changes) => {
+                    changes.iter().for_each(|l| reply += &format!("{}\n", l));
+                    reply += "OK\n";
+                }
+                None => break,
+            },
+            // Only meaningful while idle
+            ("noidle", None) => {}
+            (_, None) => match handle(&words, &send_event) {
+                Ok(response) => {
+                    response.iter().for_each(|l| reply += &format!("{}\n", l));
+                    reply += "OK\n";
+                }
+                Err(ack) => reply = format!("{}\n", ack.line(0, command)),
+            },
+        }
+    }
+    debug!("MPD client disconnected : {}", peer);
+}
+
+/// Listen for MPD clients on `address`, serving each connection on its own thread.
+pub fn start(address: impl ToSocketAddrs, send_event: EventSender) -> std::io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("MPD server listening on {}", listener.local_addr()?);
+    thread::Builder::new()
+        .name("MPD server".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let send_event = send_event.clone();
+                        let _ = thread::Builder::new()
+                            .name("MPD connection".to_string())
+                            .spawn(move || serve(stream, send_event));
+                    }
+                    Err(error) => warn!("MPD connection failed : {}", error),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[test]
+fn test_split() {
+    assert_eq!(split("play"), ["play"]);
+    assert_eq!(split("  setvol \"50\" "), ["setvol", "50"]);
+    assert_eq!(
+        split(r#"find "Artist" "A \"B\" C""#),
+        ["find", "Artist", "A \"B\" C"]
+    );
+}
+
+#[test]
+fn test_handle() {
+    let _player_state = super::mock::lock_player_state();
+    let (send_event, sent) = super::mock::recorder();
+    let run = |line: &str| {
+        let words = split(line);
+        handle(&words, &send_event).map_err(|ack| ack.line(0, &words[0]))
+    };
+
+    let status = run("status").unwrap_or_default();
+    let fields: Vec<&str> = status.iter().filter_map(|l| l.split(':').next()).collect();
+    assert_eq!(
+        fields,
+        [
+            "volume",
+            "repeat",
+            "random",
+            "single",
+            "consume",
+            "playlist",
+            "playlistlength",
+            "state"
+        ]
+    );
+    // Nothing plays or is discovered in tests
+    assert!(status.contains(&"state: stop".to_string()));
+    assert!(status.contains(&"playlistlength: 0".to_string()));
+
+    assert_eq!(run("next"), Ok(Vec::new()));
+    assert_eq!(run("pause 1"), Ok(Vec::new()));
+    assert_eq!(run("stop"), Ok(Vec::new()));
+    assert_eq!(run("setvol 150"), Ok(Vec::new()));
+    assert_eq!(run("play"), Ok(Vec::new()));
+    for position in [0, 3] {
+        assert_eq!(
+            run(&format!("play {}", position)),
+            Err("ACK [50@0] {play} No such song".to_string())
+        );
+    }
+    assert_eq!(
+        run("setvol loud"),
+        Err("ACK [2@0] {setvol} Invalid argument: loud".to_string())
+    );
+    assert_eq!(
+        run("repeat"),
+        Err("ACK [2@0] {repeat} missing argument".to_string())
+    );
+    assert_eq!(
+        run("rewind"),
+        Err("ACK [5@0] {rewind} unknown command \"rewind\"".to_string())
+    );
+    assert_eq!(
+        *sent.lock().unwrap(),
+        [
+            "UserSkip",
+            "Control(Pause)",
+            "Control(Pause)",
+            "Control(SetVolume(1.0))",
+            "Control(Play)"
+        ]
+    );
+    assert_eq!(
+        Ack::new(ACK_UNKNOWN, "oops").line(2, "status"),
+        "ACK [5@2] {status} oops"
+    );
+}
+
+#[test]
+fn test_switch_repeat() {
+    use Repeat::*;
+
+    let cases = [
+        (Off, "repeat", true, Album),
+        (One, "repeat", true, One),
+        (One, "repeat", false, Off),
+        (Album, "repeat", false, Off),
+        (Off, "single", true, One),
+        (Album, "single", true, One),
+        (One, "single", false, Off),
+        (Album, "single", false, Album),
+    ];
+    for (current, command, on, expected) in cases {
+        assert_eq!(
+            switch_repeat(current, command, on),
+            expected,
+            "{} {} from {}",
+            command,
+            on,
+            current
+        );
+    }
+}
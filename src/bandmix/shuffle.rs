@@ -0,0 +1,168 @@
+//! Reorders tracks on their way into the track list. Nothing already listed is moved, so
+//! previous and the history always follow what played.
+
+use std::collections::VecDeque;
+
+use rand::seq::SliceRandom;
+
+use crate::bandcamp::models::{AlbumID, TrackID};
+
+/// How tracks are reordered, written as `off` or a comma separated list of `album`,
+/// `interleave:K` and `spacing:N`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Shuffle {
+    /// Shuffle the tracks of each album
+    pub within_album: bool,
+    /// Albums whose tracks are mixed together, 0 or 1 keeps albums apart
+    pub interleave: usize,
+    /// Tracks played before an artist is heard again, 0 allows them back to back
+    pub artist_spacing: usize,
+}
+
+impl std::fmt::Display for Shuffle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if self.within_album {
+            parts.push("album".to_string());
+        }
+        if self.interleave > 1 {
+            parts.push(format!("interleave:{}", self.interleave));
+        }
+        if self.artist_spacing > 0 {
+            parts.push(format!("spacing:{}", self.artist_spacing));
+        }
+        if parts.is_empty() {
+            f.write_str("off")
+        } else {
+            f.write_str(&parts.join(","))
+        }
+    }
+}
+
+impl std::str::FromStr for Shuffle {
+    type Err = String;
+
+    fn from_str(shuffle: &str) -> Result<Self, Self::Err> {
+        let mut settings = Shuffle::default();
+        for part in shuffle.split(',').map(str::trim) {
+            let count = |count: &str| {
+                count
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid shuffle count: {}", count))
+            };
+            match part.split_once(':') {
+                None if part == "off" => {}
+                None if part == "album" => settings.within_album = true,
+                Some(("interleave", albums)) => settings.interleave = count(albums)?,
+                Some(("spacing", tracks)) => settings.artist_spacing = count(tracks)?,
+                _ => return Err(format!("Unknown shuffle: {}", part)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// A track chosen for the track list but not yet added
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pending {
+    pub album_id: AlbumID,
+    pub track_id: TrackID,
+    pub artist: String,
+}
+
+/// Tracks waiting to be put in order
+#[derive(Default)]
+pub struct Staging {
+    /// Each album's tracks, in the order they will be taken
+    albums: Vec<(AlbumID, VecDeque<Pending>)>,
+    /// Held back for a later batch, as their artist was heard too recently
+    held: VecDeque<Pending>,
+    /// Artists of the tracks most recently taken
+    recent: VecDeque<String>,
+}
+
+impl Staging {
+    pub fn add(&mut self, album_id: AlbumID, mut tracks: Vec<Pending>, shuffle: &Shuffle) {
+        if tracks.is_empty() {
+            return;
+        }
+        if shuffle.within_album {
+            tracks.shuffle(&mut rand::thread_rng());
+        }
+        match self.albums.last_mut() {
+            Some((id, album)) if *id == album_id => album.extend(tracks),
+            _ => self.albums.push((album_id, tracks.into())),
+        }
+    }
+
+    /// Enough albums are staged to interleave
+    pub fn ready(&self, shuffle: &Shuffle) -> bool {
+        self.albums.len() >= shuffle.interleave.max(1)
+    }
+
+    /// Every staged track in play order, except any held back to space out an artist. With
+    /// `force` nothing is held back, for when the track list has run dry.
+    pub fn take(&mut self, shuffle: &Shuffle, force: bool) -> Vec<Pending> {
+        let mut candidates = std::mem::take(&mut self.held);
+        let mut albums: Vec<VecDeque<Pending>> =
+            self.albums.drain(..).map(|(_, album)| album).collect();
+        while albums.iter().any(|album| !album.is_empty()) {
+            candidates.extend(albums.iter_mut().filter_map(VecDeque::pop_front));
+        }
+
+        let spacing = shuffle.artist_spacing;
+        let mut ordered = Vec::new();
+        while !candidates.is_empty() {
+            let index = match candidates
+                .iter()
+                .position(|track| !self.recent.contains(&track.artist))
+            {
+                Some(index) => index,
+                // Better to wait for another artist than repeat this one now
+                None if candidates.len() <= spacing && !force => break,
+                None => 0,
+            };
+            let Some(track) = candidates.remove(index) else {
+                break;
+            };
+            self.recent.push_back(track.artist.clone());
+            while self.recent.len() > spacing {
+                self.recent.pop_front();
+            }
+            ordered.push(track);
+        }
+        self.held = candidates;
+        ordered
+    }
+}
+
+#[test]
+fn test_staging() {
+    let track = |album_id, track_id, artist: &str| Pending {
+        album_id,
+        track_id,
+        artist: artist.to_string(),
+    };
+    let ids =
+        |tracks: Vec<Pending>| -> Vec<TrackID> { tracks.iter().map(|t| t.track_id).collect() };
+
+    let shuffle: Shuffle = "interleave:2,spacing:1".parse().unwrap();
+    assert_eq!(shuffle.to_string(), "interleave:2,spacing:1");
+    let mut staging = Staging::default();
+    staging.add(1, vec![track(1, 11, "a"), track(1, 12, "a")], &shuffle);
+    assert!(!staging.ready(&shuffle));
+    staging.add(2, vec![track(2, 21, "b"), track(2, 22, "b")], &shuffle);
+    assert!(staging.ready(&shuffle));
+    assert_eq!(ids(staging.take(&shuffle, false)), [11, 21, 12, 22]);
+
+    // The same artist twice in a row waits for a track by someone else
+    staging.add(3, vec![track(3, 31, "b"), track(3, 32, "c")], &shuffle);
+    assert_eq!(ids(staging.take(&shuffle, false)), [32, 31]);
+    // Only as many as the spacing are held back for the next batch
+    staging.add(4, vec![track(4, 41, "b"), track(4, 42, "b")], &shuffle);
+    assert_eq!(ids(staging.take(&shuffle, false)), [41]);
+    assert_eq!(ids(staging.take(&shuffle, true)), [42]);
+
+    assert_eq!("off".parse::<Shuffle>(), Ok(Shuffle::default()));
+    assert!("sideways".parse::<Shuffle>().is_err());
+}
@@ -0,0 +1,69 @@
+use std::{sync::RwLock, time::Duration};
+
+use once_cell::sync::Lazy;
+use tracing::warn;
+
+use super::discovery::Entry;
+
+/// Snapshot of the player, kept up to date by the main loop for control surfaces that run on
+/// other threads.
+#[derive(Clone, Default)]
+pub struct Status {
+    pub track: Entry,
+    pub paused: bool,
+    pub position: Duration,
+    pub duration: Option<Duration>,
+    pub volume: f32,
+    pub device: String,
+    /// Why the current track is not playing
+    pub error: Option<String>,
+}
+
+impl Status {
+    /// `play`, `pause`, or `stop` when there is no track to play
+    pub fn state(&self) -> &'static str {
+        if self.track.url.is_empty() {
+            "stop"
+        } else if self.paused {
+            "pause"
+        } else {
+            "play"
+        }
+    }
+}
+
+/// A duration as minutes and seconds, such as `3:07`
+pub fn format_time(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+static STATUS: Lazy<RwLock<Status>> = Lazy::new(Default::default);
+
+pub fn get() -> Status {
+    match STATUS.read() {
+        Ok(status) => status.clone(),
+        Err(_) => {
+            warn!("Failed to read status");
+            Status::default()
+        }
+    }
+}
+
+pub fn update(f: impl FnOnce(&mut Status)) {
+    match STATUS.write() {
+        Ok(mut status) => f(&mut status),
+        Err(_) => warn!("Failed to write status"),
+    }
+}
+
+#[test]
+fn test_state() {
+    let mut status = Status::default();
+    assert_eq!(status.state(), "stop");
+    status.track.url = "https://t4.bcbits.com/stream/aaa/mp3-128/1001".to_string();
+    assert_eq!(status.state(), "play");
+    status.paused = true;
+    assert_eq!(status.state(), "pause");
+    assert_eq!(format_time(Duration::from_secs(187)), "3:07");
+}
@@ -1,10 +1,198 @@
-// use std::time::Duration;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use rodio::{OutputStream, OutputStreamHandle, Sink};
-// use stream_download::http::HttpStream;
+use rodio::{
+    cpal::{self, traits::HostTrait},
+    source::SeekError,
+    Device, DeviceTrait, OutputStream, OutputStreamHandle, Sink, Source,
+};
+use stream_download::http::{reqwest::Client, HttpStream, HttpStreamError};
+use stream_download::source::SourceStream;
 use stream_download::storage::temp::TempStorageProvider;
-use stream_download::{Settings, StreamDownload};
-use tracing::debug;
+use stream_download::{Settings, StreamDownload, StreamInitializationError};
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+use super::{
+    cache::AudioCache,
+    discovery::Entry,
+    dsp::{Dsp, DspControl},
+    icecast::Broadcast,
+};
+use crate::bandcamp::{client, models::TrackID};
+
+/// How far short of its duration a track may end and still count as played to the end
+const TRUNCATION_TOLERANCE: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug, Clone)]
+pub enum StreamError {
+    #[error("no stream url")]
+    NoStreamUrl,
+    #[error("invalid stream url {0}")]
+    InvalidUrl(String),
+    #[error("network error: {0}")]
+    Network(String),
+    #[error("HTTP status {0}")]
+    HttpStatus(u16),
+    #[error("failed to decode: {0}")]
+    Decode(String),
+    #[error("stream ended after {played:?} of {total:?}")]
+    Truncated { played: Duration, total: Duration },
+    #[error("cache error: {0}")]
+    Cache(String),
+}
+
+impl StreamError {
+    /// Whether the track itself is at fault, rather than the connection
+    pub fn is_track_fault(&self) -> bool {
+        matches!(
+            self,
+            StreamError::NoStreamUrl | StreamError::Decode(_) | StreamError::HttpStatus(404)
+        )
+    }
+
+    /// Whether the stream url has expired, so a fresh one has to be scraped
+    pub fn is_expired(&self) -> bool {
+        matches!(self, StreamError::HttpStatus(403 | 410))
+    }
+}
+
+impl From<HttpStreamError<Client>> for StreamError {
+    fn from(error: HttpStreamError<Client>) -> StreamError {
+        match error {
+            HttpStreamError::FetchFailure(error) => StreamError::Network(error.to_string()),
+            HttpStreamError::ResponseFailure(error) => match error.source().status() {
+                Some(status) => StreamError::HttpStatus(status.as_u16()),
+                None => StreamError::Network(error.to_string()),
+            },
+        }
+    }
+}
+
+impl From<StreamInitializationError<HttpStream<Client>>> for StreamError {
+    fn from(error: StreamInitializationError<HttpStream<Client>>) -> StreamError {
+        match error {
+            StreamInitializationError::StorageCreationFailure(error) => {
+                StreamError::Cache(error.to_string())
+            }
+            StreamInitializationError::StreamCreationFailure(error) => error.into(),
+        }
+    }
+}
+
+/// Called once a track is over with its id, and an error if it ended early
+pub type FinishedCallback = Arc<dyn Fn(TrackID, Result<(), StreamError>) + Send + Sync>;
+
+/// Calls back when its source runs out, but not when it is dropped early, such as by a skip.
+struct Finished<S> {
+    input: S,
+    id: TrackID,
+    callback: Option<FinishedCallback>,
+    played: f64,
+}
+
+impl<S: Source> Iterator for Finished<S>
+where
+    S::Item: rodio::Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let Some(sample) = self.input.next() else {
+            if let Some(callback) = self.callback.take() {
+                let played = Duration::from_secs_f64(self.played);
+                match self.input.total_duration() {
+                    Some(total) if played + TRUNCATION_TOLERANCE < total => {
+                        callback(self.id, Err(StreamError::Truncated { played, total }))
+                    }
+                    _ => callback(self.id, Ok(())),
+                }
+            }
+            return None;
+        };
+        self.played += 1.0 / (self.input.sample_rate() as f64 * self.input.channels() as f64);
+        Some(sample)
+    }
+}
+
+impl<S: Source> Source for Finished<S>
+where
+    S::Item: rodio::Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.played = pos.as_secs_f64();
+        Ok(())
+    }
+}
+
+/// Names of every output device on the default host.
+pub fn output_devices() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+        Err(error) => {
+            warn!("Failed to list output devices : {}", error);
+            Vec::new()
+        }
+    }
+}
+
+/// Find an output device by its exact name, or else by a case insensitive partial match.
+fn find_device(name: &str) -> Option<Device> {
+    let devices: Vec<Device> = cpal::default_host().output_devices().ok()?.collect();
+    let name_lower = name.to_lowercase();
+    let position = devices
+        .iter()
+        .position(|d| d.name().is_ok_and(|n| n == name))
+        .or_else(|| {
+            devices.iter().position(|d| {
+                d.name()
+                    .is_ok_and(|n| n.to_lowercase().contains(&name_lower))
+            })
+        })?;
+    devices.into_iter().nth(position)
+}
+
+fn open_output(device: Option<&str>) -> Option<(OutputStream, OutputStreamHandle, String)> {
+    if let Some(name) = device {
+        if let Some(device) = find_device(name) {
+            let device_name = device.name().unwrap_or_default();
+            match OutputStream::try_from_device(&device) {
+                Ok((stream, handle)) => return Some((stream, handle, device_name)),
+                Err(error) => warn!("Failed to open output device {} : {}", device_name, error),
+            }
+        } else {
+            warn!("Output device not found : {}", name);
+        }
+        info!("Falling back to default output device");
+    }
+    let (stream, handle) = OutputStream::try_default().ok()?;
+    let device_name = cpal::default_host()
+        .default_output_device()
+        .and_then(|d| d.name().ok())
+        .unwrap_or_default();
+    Some((stream, handle, device_name))
+}
 
 pub struct Player {
     // storage: TempStorageProvider,
@@ -12,12 +200,24 @@ pub struct Player {
     _output_stream: OutputStream,
     _output_stream_handle: OutputStreamHandle,
     pub sink: Sink,
+    pub dsp: DspControl,
+    pub cache: Option<Arc<AudioCache>>,
+    /// Listeners re-streaming whatever is played
+    pub broadcast: Option<Arc<Broadcast>>,
+    /// Keep the local output silent, such as when only streaming
+    pub muted: bool,
+    pub on_finished: Option<FinishedCallback>,
+    volume: Mutex<f32>,
+    device_name: String,
+    current: Mutex<Option<Entry>>,
+    duration: Mutex<Option<Duration>>,
 }
 
 impl Player {
-    pub fn new() -> Option<Player> {
-        let (_stream, handle) = rodio::OutputStream::try_default().ok()?;
+    pub fn new(device: Option<&str>) -> Option<Player> {
+        let (_stream, handle, device_name) = open_output(device)?;
         let sink = rodio::Sink::try_new(&handle).ok()?;
+        info!("Using output device : {}", device_name);
 
         Some(Player {
             // storage: TempStorageProvider::new(),
@@ -26,9 +226,66 @@ impl Player {
             _output_stream_handle: handle,
             // decode_buffer: ArrayQueue::new(16),
             sink,
+            dsp: DspControl::default(),
+            cache: None,
+            broadcast: None,
+            muted: false,
+            on_finished: None,
+            volume: Mutex::new(1.0),
+            device_name,
+            current: Mutex::new(None),
+            duration: Mutex::new(None),
         })
     }
 
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    /// Move playback onto another output device, `None` being the system default.
+    /// The current track is restarted on the new device at the same position.
+    pub async fn set_device(&mut self, device: Option<&str>) -> Option<()> {
+        let (stream, handle, device_name) = open_output(device)?;
+        let sink = rodio::Sink::try_new(&handle).ok()?;
+        sink.set_volume(self.sink.volume());
+        if self.sink.is_paused() {
+            sink.pause();
+        }
+        let position = self.sink.get_pos();
+        let resume = !self.sink.empty();
+        self.sink.stop();
+
+        self.sink = sink;
+        self._output_stream = stream;
+        self._output_stream_handle = handle;
+        self.device_name = device_name;
+        info!("Switched output device : {}", self.device_name);
+
+        let track = self.current.lock().ok()?.clone();
+        if let (true, Some(track)) = (resume, track) {
+            if let Err(error) = self.start(&track).await {
+                warn!("Failed to resume on new device : {}", error);
+                return None;
+            }
+            self.seek(position);
+        }
+        Some(())
+    }
+
+    /// Whether the output device went away by no longer being listed. The sink's progress is
+    /// not a sign of this, as it also stands still while the network is buffering.
+    pub fn device_lost(&self) -> bool {
+        if self.device_name.is_empty() {
+            return false;
+        }
+        let devices = output_devices();
+        if !devices.is_empty() && !devices.contains(&self.device_name) {
+            warn!("Output device disappeared : {}", self.device_name);
+            return true;
+        }
+        false
+    }
+
     // TODO: decouple start and decoding of stream
     // async fn get_decoded_url(
     //     url: String,
@@ -44,38 +301,138 @@ impl Player {
     //     Some(decode)
     // }
 
-    pub async fn start(&self, url: &str) -> Option<()> {
+    /// Start playing a track, from the audio cache if it is there. What was playing stops
+    /// even if the track fails to start, so it does not play on under the new track's name.
+    pub async fn start(&self, track: &Entry) -> Result<(), StreamError> {
+        let result = self.open(track).await;
+        if result.is_err() {
+            self.silence();
+        }
+        result
+    }
+
+    /// Drop whatever source is playing
+    pub fn silence(&self) {
+        if let Ok(mut duration) = self.duration.lock() {
+            *duration = None;
+        }
+        if !self.sink.empty() {
+            self.sink.skip_one();
+        }
+    }
+
+    async fn open(&self, track: &Entry) -> Result<(), StreamError> {
         // let runtime = tokio::runtime::Runtime::new().unwrap();
         // let decode = runtime
         //     .block_on(Self::get_decoded_url(url.to_owned()))
         //     .unwrap();
         // self.sink.append(result);
-        let url_string = url.to_string();
+        if let Ok(mut current) = self.current.lock() {
+            *current = Some(track.clone());
+        }
+
+        if let Some(path) = self.cache.as_ref().and_then(|c| c.get(track.id)) {
+            debug!("Playing from cache: {}", path.display());
+            let file = File::open(path).map_err(|e| StreamError::Cache(e.to_string()))?;
+            return self.append(BufReader::new(file));
+        }
+
+        if track.url.is_empty() {
+            return Err(StreamError::NoStreamUrl);
+        }
+        let url = track
+            .url
+            .parse()
+            .map_err(|_| StreamError::InvalidUrl(track.url.clone()))?;
+        let url_string = track.url.clone();
         // println!("Reading: {}", url);
-        let reader = StreamDownload::new_http(
-            url.parse().ok()?,
-            TempStorageProvider::new(),
-            Settings::default().on_progress(move |_client, stream_state| {
-                if stream_state.phase == stream_download::StreamPhase::Complete {
-                    debug!("Downloading Complete: {}", url_string);
-                };
-            }),
-        )
-        .await
-        .ok()?;
+        if let Some(cache) = &self.cache {
+            let id = track.id;
+            let cache_clone = Arc::clone(cache);
+            let storage = cache.storage(id);
+            let part = storage.part().to_path_buf();
+            let stream = HttpStream::new(client::stream_client(), url).await?;
+            let reader = StreamDownload::from_stream(
+                stream,
+                storage,
+                Settings::default().on_progress(
+                    move |stream: &HttpStream<Client>, stream_state| {
+                        if stream_state.phase == stream_download::StreamPhase::Complete {
+                            debug!("Downloading Complete: {}", url_string);
+                            cache_clone.commit(id, &part, stream.content_length());
+                        };
+                    },
+                ),
+            )
+            .await?;
+            self.append(reader)
+        } else {
+            let stream = HttpStream::new(client::stream_client(), url).await?;
+            let reader = StreamDownload::from_stream(
+                stream,
+                TempStorageProvider::new(),
+                Settings::default().on_progress(move |_client, stream_state| {
+                    if stream_state.phase == stream_download::StreamPhase::Complete {
+                        debug!("Downloading Complete: {}", url_string);
+                    };
+                }),
+            )
+            .await?;
+            self.append(reader)
+        }
+    }
+
+    fn append<R>(&self, reader: R) -> Result<(), StreamError>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        match &self.broadcast {
+            Some(broadcast) => {
+                let title = self
+                    .current
+                    .lock()
+                    .ok()
+                    .and_then(|c| c.as_ref().map(|t| format!("{} - {}", t.artist, t.name)))
+                    .unwrap_or_default();
+                self.decode(broadcast.track(title, reader))
+            }
+            None => self.decode(reader),
+        }
+    }
+
+    fn decode<R>(&self, reader: R) -> Result<(), StreamError>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
         // println!("Decoding: {}", url);
-        let decode = rodio::Decoder::new(reader).ok()?;
+        let decode = rodio::Decoder::new(reader).map_err(|e| StreamError::Decode(e.to_string()))?;
+        if let Ok(mut duration) = self.duration.lock() {
+            *duration = decode.total_duration();
+        }
+        let id = self
+            .current
+            .lock()
+            .ok()
+            .and_then(|c| c.as_ref().map(|t| t.id))
+            .unwrap_or_default();
+        let source = Finished {
+            input: Dsp::new(decode.convert_samples(), self.dsp.clone()),
+            id,
+            callback: self.on_finished.clone(),
+            played: 0.0,
+        };
         // self.sink.pause();
         let _playing = !self.sink.is_paused();
         let empty = self.sink.empty();
-        self.sink.append(decode);
+        self.sink.append(source);
         if !empty {
             self.sink.skip_one();
         }
-        debug!("New Source Playing: {}", url);
+        debug!("New Source Playing");
         // self.play();
-        Some(())
+        Ok(())
     }
+
     pub fn play(&self) {
         self.sink.play()
     }
@@ -85,6 +442,31 @@ impl Player {
     pub fn stop(&self) {
         self.sink.stop()
     }
+    pub fn volume(&self) -> f32 {
+        self.volume.lock().map(|v| *v).unwrap_or_default()
+    }
+    pub fn set_volume(&self, volume: f32) {
+        let volume = volume.clamp(0.0, 1.0);
+        if let Ok(mut current) = self.volume.lock() {
+            *current = volume;
+        }
+        self.sink.set_volume(if self.muted { 0.0 } else { volume })
+    }
+    pub fn seek(&self, position: Duration) {
+        if let Err(error) = self.sink.try_seek(position) {
+            debug!("Failed to seek to {:?} : {}", position, error);
+        }
+    }
+    pub fn position(&self) -> Duration {
+        self.sink.get_pos()
+    }
+    /// As decoded, or as listed on Bandcamp for streams that do not say
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration.lock().ok().and_then(|d| *d).or_else(|| {
+            let current = self.current.lock().ok()?;
+            current.as_ref()?.duration.map(Duration::from_secs_f64)
+        })
+    }
     pub fn is_paused(&self) -> bool {
         self.sink.is_paused()
     }
@@ -95,3 +477,45 @@ impl Player {
         self.sink.empty()
     }
 }
+
+#[test]
+fn test_finished_callback() {
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let source = |calls: &Arc<AtomicUsize>| {
+        let calls = Arc::clone(calls);
+        Finished {
+            input: rodio::buffer::SamplesBuffer::new(1, 44100, vec![0.0f32; 4]),
+            id: 7,
+            callback: Some(Arc::new(move |id, result: Result<(), StreamError>| {
+                assert_eq!(id, 7);
+                assert!(result.is_ok());
+                calls.fetch_add(1, Relaxed);
+            })),
+            played: 0.0,
+        }
+    };
+
+    let mut skipped = source(&calls);
+    skipped.next();
+    drop(skipped);
+    assert_eq!(calls.load(Relaxed), 0);
+
+    let mut played = source(&calls);
+    while played.next().is_some() {}
+    played.next();
+    assert_eq!(calls.load(Relaxed), 1);
+}
+
+#[test]
+fn test_track_fault() {
+    assert!(StreamError::HttpStatus(404).is_track_fault());
+    assert!(StreamError::NoStreamUrl.is_track_fault());
+    // An expired link says nothing about the track
+    for status in [403, 410] {
+        assert!(StreamError::HttpStatus(status).is_expired());
+        assert!(!StreamError::HttpStatus(status).is_track_fault());
+    }
+    assert!(!StreamError::Network("reset".to_string()).is_track_fault());
+}
@@ -0,0 +1,432 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, Gauge, List, ListItem, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use souvlaki::MediaControlEvent;
+use tracing::warn;
+use tracing_subscriber::fmt::MakeWriter;
+
+use super::{
+    cache::AudioCache,
+    discovery::{self, DiscoveryError},
+    dsp::DspControl,
+    events::{self, EventSender},
+    export::{self, ExportSettings},
+    shuffle::Shuffle,
+    status::{self, format_time, Status},
+};
+
+const LOG_LINES: usize = 500;
+const QUEUE_LENGTH: usize = 16;
+const VOLUME_STEP: f64 = 0.05;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static LOG: Lazy<Mutex<VecDeque<String>>> = Lazy::new(Default::default);
+static THREAD: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(Default::default);
+
+/// Print a line to the log pane while the TUI is up, otherwise to stdout.
+pub fn print(line: String) {
+    if !ACTIVE.load(Relaxed) {
+        println!("{}", line);
+        return;
+    }
+    if let Ok(mut log) = LOG.lock() {
+        for line in line.lines() {
+            if log.len() >= LOG_LINES {
+                log.pop_front();
+            }
+            log.push_back(line.to_string());
+        }
+    }
+}
+
+/// Tracing writer that sends logs to the log pane.
+#[derive(Clone, Copy, Default)]
+pub struct LogWriter;
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        print(String::from_utf8_lossy(buf).trim_end().to_string());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        *self
+    }
+}
+
+/// Everything the TUI can act on besides the player events handled by `main`
+pub struct Tui {
+    pub send_event: EventSender,
+    pub dsp: DspControl,
+    pub export: ExportSettings,
+    pub cache: Option<Arc<AudioCache>>,
+}
+
+pub fn start(tui: Tui) {
+    ACTIVE.store(true, Relaxed);
+    let thread = thread::Builder::new()
+        .name("TUI".to_string())
+        .spawn(move || {
+            let terminal = ratatui::init();
+            if let Err(error) = run(terminal, tui) {
+                warn!("TUI stopped : {}", error);
+            }
+            ratatui::restore();
+        });
+    match thread {
+        Ok(thread) => {
+            if let Ok(mut handle) = THREAD.lock() {
+                *handle = Some(thread);
+            }
+        }
+        Err(error) => {
+            ACTIVE.store(false, Relaxed);
+            warn!("Failed to start TUI : {}", error);
+        }
+    }
+}
+
+pub fn stop() {
+    if !ACTIVE.swap(false, Relaxed) {
+        return;
+    }
+    let thread = THREAD.lock().ok().and_then(|mut t| t.take());
+    if let Some(thread) = thread {
+        if thread.join().is_err() {
+            warn!("TUI thread error on join");
+        }
+    }
+}
+
+/// Text shown in place of the log
+#[derive(Clone, Copy, PartialEq)]
+enum Panel {
+    Lyrics,
+    About,
+}
+
+fn run(mut terminal: DefaultTerminal, tui: Tui) -> io::Result<()> {
+    let mut log_scroll: usize = 0;
+    // Replaces the log while shown, scrolled down this many lines
+    let mut panel: Option<(Panel, u16)> = None;
+    let toggle = |panel: &mut Option<(Panel, u16)>, shown: Panel| {
+        *panel = match panel {
+            Some((current, _)) if *current == shown => None,
+            _ => Some((shown, 0)),
+        }
+    };
+
+    while ACTIVE.load(Relaxed) {
+        let status = status::get();
+        terminal.draw(|frame| draw(frame, &status, &tui, log_scroll, panel))?;
+
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let send = &tui.send_event;
+        match key.code {
+            KeyCode::Char(' ') => send(MediaControlEvent::Toggle.into()),
+            KeyCode::Char('n') | KeyCode::Right => send(MediaControlEvent::Next.into()),
+            KeyCode::Char('b') | KeyCode::Left => send(MediaControlEvent::Previous.into()),
+            KeyCode::Char('+') | KeyCode::Char('=') => send(
+                MediaControlEvent::SetVolume((status.volume as f64 + VOLUME_STEP).min(1.0)).into(),
+            ),
+            KeyCode::Char('-') => send(
+                MediaControlEvent::SetVolume((status.volume as f64 - VOLUME_STEP).max(0.0)).into(),
+            ),
+            KeyCode::Char('l') => report("LIKED", discovery::like_current_track(), &status),
+            KeyCode::Char('d') => {
+                report("DISLIKED", discovery::dislike_current_track(), &status);
+                send(MediaControlEvent::Next.into());
+            }
+            KeyCode::Char('u') => report("UNMARKED", discovery::unmark_current_track(), &status),
+            KeyCode::Char('s') => save(&tui, status.track.clone()),
+            KeyCode::Char('p') => discovery::set_play_mode(discovery::play_mode().next()),
+            KeyCode::Char('z') => {
+                let shuffle = discovery::shuffle();
+                discovery::set_shuffle(Shuffle {
+                    within_album: !shuffle.within_album,
+                    ..shuffle
+                })
+            }
+            KeyCode::Char('N') => match discovery::skip_album() {
+                Ok(position) => send(events::Event::SkipAlbum {
+                    position,
+                    mark: None,
+                }),
+                Err(error) => print(format!("Failed to skip album: {}", error)),
+            },
+            KeyCode::Char('B') => match discovery::previous_album() {
+                Ok(position) => send(events::Event::Jump {
+                    position,
+                    mark_skipped: false,
+                }),
+                Err(error) => print(format!("Failed to go back an album: {}", error)),
+            },
+            KeyCode::Char('r') => discovery::set_repeat(discovery::repeat().next()),
+            KeyCode::Char('R') => match discovery::album_start() {
+                Some(position) => send(events::Event::Jump {
+                    position,
+                    mark_skipped: false,
+                }),
+                None => print("Nothing to replay".to_string()),
+            },
+            KeyCode::Char('e') => tui.dsp.set_preset(tui.dsp.settings().preset.next()),
+            KeyCode::Char('m') => tui.dsp.update(|s| s.mono = !s.mono),
+            KeyCode::Char('L') => tui.dsp.update(|s| s.limiter = !s.limiter),
+            KeyCode::Char('y') => toggle(&mut panel, Panel::Lyrics),
+            KeyCode::Char('i') => toggle(&mut panel, Panel::About),
+            KeyCode::Up => match &mut panel {
+                Some((_, scroll)) => *scroll = scroll.saturating_sub(1),
+                None => log_scroll = log_scroll.saturating_add(1),
+            },
+            KeyCode::Down => match &mut panel {
+                Some((_, scroll)) => *scroll = scroll.saturating_add(1),
+                None => log_scroll = log_scroll.saturating_sub(1),
+            },
+            KeyCode::Char('q') | KeyCode::Esc => send(MediaControlEvent::Quit.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn report(action: &str, result: Result<(), DiscoveryError>, status: &Status) {
+    match result {
+        Ok(()) => print(format!("{}: {}", action, status.track.name)),
+        Err(error) => print(format!("Failed to update {}: {}", status.track.name, error)),
+    }
+}
+
+fn save(tui: &Tui, track: discovery::Entry) {
+    let export = tui.export.clone();
+    let cache = tui.cache.clone();
+    let save_track = move || match export::save(&track, &export, cache.as_deref()) {
+        Ok(path) => print(format!("SAVED: {}", path.display())),
+        Err(error) => print(format!("Failed to save {}: {}", track.name, error)),
+    };
+    let _ = thread::Builder::new()
+        .name("Save track".to_string())
+        .spawn(save_track);
+}
+
+fn draw(
+    frame: &mut Frame,
+    status: &Status,
+    tui: &Tui,
+    log_scroll: usize,
+    panel: Option<(Panel, u16)>,
+) {
+    let [now_playing, progress, volume, queue, log, help] = Layout::vertical([
+        Constraint::Length(9),
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Min(4),
+        Constraint::Percentage(35),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let track = &status.track;
+    let mut title = vec![Span::styled(
+        track.name.clone(),
+        Style::default().add_modifier(Modifier::BOLD),
+    )];
+    if discovery::liked(track.id) {
+        title.push(" ♥".red());
+    }
+    if track.has_lyrics {
+        title.push("  [lyrics]".dark_gray());
+    }
+    if track.downloadable {
+        title.push("  [download]".dark_gray());
+    }
+    let mut album = vec!["from ".dim(), Span::raw(track.album_name.clone())];
+    // Compilations credit each track to its own artist
+    if track.album_artist != track.artist {
+        album.extend([" by ".dim(), Span::raw(track.album_artist.clone())]);
+    }
+    album.push(format!("  {}", track.release_date).dim());
+    let mut origin = Vec::new();
+    if let Some(label) = &track.label {
+        origin.extend(["on ".dim(), Span::raw(label.clone())]);
+    }
+    if let Some(location) = &track.location {
+        origin.push(format!("{}{}", if origin.is_empty() { "" } else { "  " }, location).dim());
+    }
+    let mut buy: Vec<Span> = track
+        .price_text()
+        .map(|price| vec!["Digital ".dim(), Span::raw(price)])
+        .unwrap_or_default();
+    for release in track.releases.iter().filter(|r| r.format != "Digital") {
+        if !buy.is_empty() {
+            buy.push("  ".into());
+        }
+        buy.push(release.to_string().into());
+    }
+    let details = vec![
+        Line::from(title),
+        Line::from(vec!["by ".dim(), Span::raw(track.artist.clone())]),
+        Line::from(album),
+        Line::from(origin),
+        Line::from(buy),
+        // A playback error takes the place of the tags until the track plays again
+        match &status.error {
+            Some(error) => Line::from(error.clone().red()),
+            None => Line::from(track.tags.clone().unwrap_or_default().dim()),
+        },
+        Line::from(
+            format!(
+                "{} | Mode: {} | Shuffle: {} | Repeat: {} | Output: {}",
+                tui.dsp.settings(),
+                discovery::play_mode(),
+                discovery::shuffle(),
+                discovery::repeat(),
+                status.device
+            )
+            .dark_gray(),
+        ),
+    ];
+    frame.render_widget(
+        Paragraph::new(details)
+            .wrap(Wrap { trim: true })
+            .block(Block::bordered().title(if status.paused {
+                " Paused "
+            } else {
+                " Now Playing "
+            })),
+        now_playing,
+    );
+
+    let ratio = match status.duration {
+        Some(duration) if !duration.is_zero() => {
+            (status.position.as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0)
+        }
+        _ => 0.0,
+    };
+    let label = format!(
+        "{} / {}",
+        format_time(status.position),
+        status.duration.map(format_time).unwrap_or("?".to_string())
+    );
+    frame.render_widget(Gauge::default().ratio(ratio).label(label), progress);
+    frame.render_widget(
+        Gauge::default()
+            .ratio(status.volume.clamp(0.0, 1.0) as f64)
+            .label(format!("Volume {:.0}%", status.volume * 100.0))
+            .gauge_style(Style::default().dark_gray()),
+        volume,
+    );
+
+    let upcoming: Vec<ListItem> = discovery::upcoming(QUEUE_LENGTH)
+        .into_iter()
+        .map(|entry| {
+            ListItem::new(Line::from(vec![
+                Span::raw(entry.name),
+                format!(" - {} - {}", entry.artist, entry.album_name).dim(),
+            ]))
+        })
+        .collect();
+    frame.render_widget(
+        List::new(upcoming).block(Block::bordered().title(" Up Next ")),
+        queue,
+    );
+
+    match panel {
+        Some((Panel::Lyrics, scroll)) => {
+            let lyrics = match &track.lyrics {
+                Some(lyrics) => Text::raw(lyrics.clone()),
+                None if track.has_lyrics => Text::raw("Lyrics were not on the page").dim(),
+                None => Text::raw("No lyrics for this track").dim(),
+            };
+            draw_panel(frame, log, " Lyrics ", lyrics, scroll);
+        }
+        Some((Panel::About, scroll)) => {
+            let mut about = Text::default();
+            if let Some(text) = &track.about {
+                about.extend(Text::raw(text.clone()));
+            }
+            if let Some(credits) = &track.credits {
+                if !about.lines.is_empty() {
+                    about.push_line("");
+                }
+                about.push_line(Line::from("Credits").bold());
+                about.extend(Text::raw(credits.clone()));
+            }
+            if about.lines.is_empty() {
+                about = Text::raw("Nothing written about this album").dim();
+            }
+            draw_panel(frame, log, " About ", about, scroll);
+        }
+        None => draw_log(frame, log, log_scroll),
+    }
+
+    frame.render_widget(
+        Paragraph::new(
+            "space play/pause  n/→ next  b/← previous  N/B album  +/- volume  l like  d dislike  u unmark  \
+             s save  y lyrics  i about  p mode  z shuffle  r repeat  R replay  e eq  m mono  L limiter  ↑/↓ scroll  q quit",
+        )
+        .dark_gray(),
+        help,
+    );
+}
+
+fn draw_panel(frame: &mut Frame, area: Rect, title: &str, text: Text, scroll: u16) {
+    frame.render_widget(
+        Paragraph::new(text)
+            .wrap(Wrap { trim: false })
+            .scroll((scroll, 0))
+            .block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+fn draw_log(frame: &mut Frame, log: Rect, log_scroll: usize) {
+    let log_lines: Vec<Line> = match LOG.lock() {
+        Ok(lines) => {
+            let height = log.height.saturating_sub(2) as usize;
+            let end = lines.len().saturating_sub(log_scroll);
+            let start = end.saturating_sub(height);
+            lines
+                .range(start..end)
+                .map(|l| Line::from(l.clone()))
+                .collect()
+        }
+        Err(_) => Vec::new(),
+    };
+    frame.render_widget(
+        Paragraph::new(log_lines).block(Block::bordered().title(" Log ")),
+        log,
+    );
+}
@@ -0,0 +1,477 @@
+//! Embedded HTTP server with a JSON control API under `/api`, plus `/api/events` (server-sent
+//! events) and `/api/ws` (WebSocket) streams that push changes as they happen.
+
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use souvlaki::MediaControlEvent;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, warn};
+
+use super::{
+    discovery::{self, Entry},
+    events::{self, EventSender},
+    status,
+};
+use crate::bandcamp::models::TrackID;
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:6680";
+const EVENT_POLL: Duration = Duration::from_millis(250);
+const QUEUE_LENGTH: usize = 16;
+
+/// Pushed to `/api/events` and `/api/ws` subscribers
+#[derive(Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Track { position: usize, track: Box<Entry> },
+    State { paused: bool, volume: u32 },
+    Discovery { playlist_length: usize },
+}
+
+#[derive(Clone)]
+struct Server {
+    send_event: EventSender,
+    events: broadcast::Sender<Event>,
+}
+
+#[derive(Serialize)]
+struct NowPlaying {
+    state: &'static str,
+    position: usize,
+    track: Entry,
+    liked: bool,
+    elapsed: f64,
+    duration: Option<f64>,
+    volume: u32,
+    device: String,
+    play_mode: String,
+    shuffle: String,
+    repeat: String,
+}
+
+#[derive(Deserialize)]
+struct Count {
+    count: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Volume {
+    volume: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Mode {
+    mode: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShuffleMode {
+    shuffle: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RepeatMode {
+    repeat: String,
+}
+
+fn now_playing() -> NowPlaying {
+    let status = status::get();
+    NowPlaying {
+        state: status.state(),
+        position: discovery::position(),
+        liked: discovery::liked(status.track.id),
+        elapsed: status.position.as_secs_f64(),
+        duration: status.duration.map(|d| d.as_secs_f64()),
+        volume: (status.volume * 100.0).round() as u32,
+        device: status.device,
+        play_mode: discovery::play_mode().to_string(),
+        shuffle: discovery::shuffle().to_string(),
+        repeat: discovery::repeat().to_string(),
+        track: status.track,
+    }
+}
+
+async fn get_status() -> Json<NowPlaying> {
+    Json(now_playing())
+}
+
+async fn get_queue(Query(query): Query<Count>) -> Json<Vec<Entry>> {
+    Json(discovery::upcoming(query.count.unwrap_or(QUEUE_LENGTH)))
+}
+
+/// Tracks played before the current one, most recent first
+async fn get_history(Query(query): Query<Count>) -> Json<Vec<Entry>> {
+    let position = discovery::position();
+    let mut history: Vec<Entry> = discovery::playlist().into_iter().take(position).collect();
+    history.reverse();
+    history.truncate(query.count.unwrap_or(usize::MAX));
+    Json(history)
+}
+
+async fn get_likes() -> Json<Vec<TrackID>> {
+    Json(discovery::liked_ids())
+}
+
+async fn control(State(server): State<Server>, Path(action): Path<String>) -> Response {
+    let event: events::Event = match action.as_str() {
+        "play" => MediaControlEvent::Play.into(),
+        "pause" => MediaControlEvent::Pause.into(),
+        "toggle" => MediaControlEvent::Toggle.into(),
+        "next" => MediaControlEvent::Next.into(),
+        "previous" => MediaControlEvent::Previous.into(),
+        "quit" => MediaControlEvent::Quit.into(),
+        "replay" => match discovery::album_start() {
+            Some(position) => events::Event::Jump {
+                position,
+                mark_skipped: false,
+            },
+            None => return (StatusCode::CONFLICT, "Nothing to replay").into_response(),
+        },
+        "skip-album" => match discovery::skip_album() {
+            Ok(position) => events::Event::SkipAlbum {
+                position,
+                mark: None,
+            },
+            Err(error) => return (StatusCode::CONFLICT, error.to_string()).into_response(),
+        },
+        "previous-album" => match discovery::previous_album() {
+            Ok(position) => events::Event::Jump {
+                position,
+                mark_skipped: false,
+            },
+            Err(error) => return (StatusCode::CONFLICT, error.to_string()).into_response(),
+        },
+        "like" | "dislike" | "unmark" => {
+            let marked = match action.as_str() {
+                "like" => discovery::like_current_track(),
+                "dislike" => discovery::dislike_current_track(),
+                _ => discovery::unmark_current_track(),
+            };
+            if let Err(error) = marked {
+                return (StatusCode::CONFLICT, error.to_string()).into_response();
+            }
+            if action != "dislike" {
+                return Json(now_playing()).into_response();
+            }
+            MediaControlEvent::Next.into()
+        }
+        _ => return (StatusCode::NOT_FOUND, "Unknown action").into_response(),
+    };
+    (server.send_event)(event);
+    StatusCode::ACCEPTED.into_response()
+}
+
+async fn set_volume(State(server): State<Server>, Json(volume): Json<Volume>) -> StatusCode {
+    (server.send_event)(MediaControlEvent::SetVolume(volume.volume.min(100) as f64 / 100.0).into());
+    StatusCode::ACCEPTED
+}
+
+async fn get_mode() -> Json<Mode> {
+    Json(Mode {
+        mode: discovery::play_mode().to_string(),
+    })
+}
+
+async fn set_mode(Json(mode): Json<Mode>) -> Response {
+    match mode.mode.parse() {
+        Ok(mode) => {
+            discovery::set_play_mode(mode);
+            get_mode().await.into_response()
+        }
+        Err(error) => (StatusCode::BAD_REQUEST, error).into_response(),
+    }
+}
+
+async fn get_shuffle() -> Json<ShuffleMode> {
+    Json(ShuffleMode {
+        shuffle: discovery::shuffle().to_string(),
+    })
+}
+
+async fn set_shuffle(Json(shuffle): Json<ShuffleMode>) -> Response {
+    match shuffle.shuffle.parse() {
+        Ok(shuffle) => {
+            discovery::set_shuffle(shuffle);
+            get_shuffle().await.into_response()
+        }
+        Err(error) => (StatusCode::BAD_REQUEST, error).into_response(),
+    }
+}
+
+async fn get_repeat() -> Json<RepeatMode> {
+    Json(RepeatMode {
+        repeat: discovery::repeat().to_string(),
+    })
+}
+
+async fn set_repeat(Json(repeat): Json<RepeatMode>) -> Response {
+    match repeat.repeat.parse() {
+        Ok(repeat) => {
+            discovery::set_repeat(repeat);
+            get_repeat().await.into_response()
+        }
+        Err(_) => (StatusCode::BAD_REQUEST, "Unknown repeat").into_response(),
+    }
+}
+
+async fn play_position(State(server): State<Server>, Path(position): Path<usize>) -> Response {
+    if position >= discovery::playlist_length() {
+        return (StatusCode::NOT_FOUND, "No such track").into_response();
+    }
+    if position == discovery::position() {
+        (server.send_event)(MediaControlEvent::Play.into());
+        return StatusCode::ACCEPTED.into_response();
+    }
+    (server.send_event)(events::Event::Jump {
+        position,
+        mark_skipped: true,
+    });
+    StatusCode::ACCEPTED.into_response()
+}
+
+fn event_stream(events: broadcast::Receiver<Event>) -> impl Stream<Item = Event> {
+    stream::unfold(events, |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => return Some((event, events)),
+                Err(RecvError::Lagged(skipped)) => debug!("Event stream skipped {}", skipped),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+async fn get_events(
+    State(server): State<Server>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    use futures_util::StreamExt;
+
+    let events = server.events.subscribe();
+    let events = stream::iter(current())
+        .chain(event_stream(events))
+        .map(|event| {
+            Ok(sse::Event::default()
+                .json_data(&event)
+                .unwrap_or_else(|_| sse::Event::default()))
+        });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn get_ws(State(server): State<Server>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| websocket(socket, server.events.subscribe()))
+}
+
+async fn websocket(mut socket: WebSocket, mut events: broadcast::Receiver<Event>) {
+    for event in current() {
+        let Ok(text) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            return;
+        }
+    }
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let Ok(text) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// The latest of each kind of event, which new subscribers receive first
+fn current() -> [Event; 3] {
+    let status = status::get();
+    [
+        Event::Track {
+            position: discovery::position(),
+            track: Box::new(status.track),
+        },
+        Event::State {
+            paused: status.paused,
+            volume: (status.volume * 100.0).round() as u32,
+        },
+        Event::Discovery {
+            playlist_length: discovery::playlist_length(),
+        },
+    ]
+}
+
+/// Compare the player against what was last pushed and broadcast any differences
+async fn watch(events: broadcast::Sender<Event>) {
+    let mut last = current();
+    let mut interval = tokio::time::interval(EVENT_POLL);
+    loop {
+        interval.tick().await;
+        let now = current();
+        for (last, now) in last.iter_mut().zip(now) {
+            if *last != now {
+                let _ = events.send(now.clone());
+                *last = now;
+            }
+        }
+    }
+}
+
+fn router(server: Server) -> Router {
+    Router::new()
+        .route("/api/status", get(get_status))
+        .route("/api/queue", get(get_queue))
+        .route("/api/history", get(get_history))
+        .route("/api/likes", get(get_likes))
+        .route("/api/volume", post(set_volume).put(set_volume))
+        .route("/api/mode", get(get_mode).post(set_mode).put(set_mode))
+        .route(
+            "/api/repeat",
+            get(get_repeat).post(set_repeat).put(set_repeat),
+        )
+        .route(
+            "/api/shuffle",
+            get(get_shuffle).post(set_shuffle).put(set_shuffle),
+        )
+        .route("/api/play/:position", post(play_position))
+        .route("/api/events", get(get_events))
+        .route("/api/ws", get(get_ws))
+        .route("/api/:action", post(control))
+        .with_state(server)
+}
+
+/// Serve the API on `address` from the current tokio runtime.
+pub async fn start(address: SocketAddr, send_event: EventSender) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!("HTTP API listening on http://{}", listener.local_addr()?);
+    let (events, _) = broadcast::channel(64);
+    tokio::spawn(watch(events.clone()));
+    let app = router(Server { send_event, events });
+    tokio::spawn(async move {
+        if let Err(error) = axum::serve(listener, app).await {
+            warn!("HTTP API stopped : {}", error);
+        }
+    });
+    Ok(())
+}
+
+#[test]
+fn test_router() {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    let _player_state = super::mock::lock_player_state();
+    let (send_event, sent) = super::mock::recorder();
+    let app = router(Server {
+        send_event,
+        events: broadcast::channel(1).0,
+    });
+    let request = |method: &str, uri: &str, body: Option<&str>| {
+        let builder = Request::builder().method(method).uri(uri);
+        match body {
+            Some(body) => builder
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            None => builder.body(Body::empty()),
+        }
+        .unwrap()
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let send = |request: Request<Body>| {
+        runtime.block_on(async {
+            let response = app.clone().oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        })
+    };
+
+    let (status, body) = send(request("GET", "/api/status", None));
+    assert_eq!(status, StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(json["state"], "stop");
+    assert_eq!(json["position"], 0);
+    assert!(json["track"].is_object());
+
+    let (status, body) = send(request("GET", "/api/queue?count=4", None));
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "[]"));
+    let (status, _) = send(request("GET", "/api/queue?count=few", None));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(request("PUT", "/api/volume", Some(r#"{"volume": 150}"#)));
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let (status, _) = send(request("PUT", "/api/volume", Some(r#"{"volume": "loud"}"#)));
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, body) = send(request(
+        "PUT",
+        "/api/mode",
+        Some(r#"{"mode": "sampler:2"}"#),
+    ));
+    assert_eq!(
+        (status, body.as_str()),
+        (StatusCode::OK, r#"{"mode":"sampler:2"}"#)
+    );
+    let (status, _) = send(request("PUT", "/api/mode", Some(r#"{"mode": "loop"}"#)));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    discovery::set_play_mode(discovery::PlayMode::default());
+
+    let (status, body) = send(request("PUT", "/api/repeat", Some(r#"{"repeat": "one"}"#)));
+    assert_eq!(
+        (status, body.as_str()),
+        (StatusCode::OK, r#"{"repeat":"one"}"#)
+    );
+    let (status, _) = send(request(
+        "PUT",
+        "/api/repeat",
+        Some(r#"{"repeat": "forever"}"#),
+    ));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    discovery::set_repeat(discovery::Repeat::default());
+
+    // Nothing is discovered in tests, so there is no track to play
+    for position in [0, 3] {
+        let (status, _) = send(request("POST", &format!("/api/play/{}", position), None));
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+    let (status, _) = send(request("POST", "/api/play/last", None));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(request("POST", "/api/next", None));
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let (status, _) = send(request("POST", "/api/rewind", None));
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(request("POST", "/api/replay", None));
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    assert_eq!(
+        *sent.lock().unwrap(),
+        ["Control(SetVolume(1.0))", "UserSkip"]
+    );
+}
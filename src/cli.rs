@@ -0,0 +1,257 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use tracing::Level;
+
+use crate::{
+    bandcamp::api::Genre,
+    bandmix::{
+        discovery::{AlbumMark, PlayMode, Repeat},
+        dsp::Preset,
+        icecast, mpd,
+        shuffle::Shuffle,
+        web,
+    },
+    config::Config,
+};
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Config file to read in place of config.toml in the platform config directory
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Control socket used by `daemon` and `ctl`
+    #[arg(long, global = true)]
+    pub socket: Option<PathBuf>,
+
+    /// Run the full screen terminal interface
+    #[arg(long, overrides_with = "no_tui")]
+    pub tui: bool,
+
+    /// Use the plain terminal, even if the config turns on `tui`
+    #[arg(long, overrides_with = "tui")]
+    pub no_tui: bool,
+
+    /// Serve MPD clients on this address, 127.0.0.1:6600 when given without one
+    #[arg(long, num_args = 0..=1, default_missing_value = mpd::DEFAULT_ADDRESS)]
+    pub mpd: Option<String>,
+
+    /// Serve the HTTP API on this address, 127.0.0.1:6680 when given without one
+    #[arg(long, num_args = 0..=1, default_missing_value = web::DEFAULT_ADDRESS)]
+    pub http: Option<SocketAddr>,
+
+    /// Re-stream the audio over HTTP on this address for Icecast clients, 0.0.0.0:8000 when
+    /// given without one
+    #[arg(long, num_args = 0..=1, default_missing_value = icecast::DEFAULT_ADDRESS)]
+    pub stream: Option<String>,
+
+    /// Only play through `--stream`, keeping the local output silent
+    #[arg(long, overrides_with = "no_stream_only")]
+    pub stream_only: bool,
+
+    /// Play locally as well as through `--stream`
+    #[arg(long, overrides_with = "stream_only")]
+    pub no_stream_only: bool,
+
+    /// Output device to play on, by name or part of a name
+    #[arg(long)]
+    pub device: Option<String>,
+
+    /// List the available output devices and exit
+    #[arg(long)]
+    pub list_devices: bool,
+
+    /// Keep played audio in a persistent cache of this many megabytes, 0 disables it
+    #[arg(long)]
+    pub audio_cache: Option<u64>,
+
+    /// Directory for the audio cache, defaults to the platform cache directory
+    #[arg(long)]
+    pub audio_cache_dir: Option<PathBuf>,
+
+    /// Directory that `save` writes tracks to, defaults to the platform music directory
+    #[arg(long)]
+    pub save_dir: Option<PathBuf>,
+
+    /// Filename template for saved tracks, using {artist}, {album}, {title}, {num} and {date},
+    /// defaults to {artist}/{album}/{num} - {title}
+    #[arg(long)]
+    pub save_template: Option<String>,
+
+    /// Discover genre to play, such as electronic or hip-hop-rap
+    #[arg(long)]
+    pub genre: Option<Genre>,
+
+    /// Tracks played from each album: album (the default), featured, featured-first or
+    /// sampler[:count]
+    #[arg(long)]
+    pub play_mode: Option<PlayMode>,
+
+    /// Reorder tracks as they are queued: off, or any of album (within each album),
+    /// interleave:K (mixing K albums) and spacing:N (N tracks between one artist's), comma
+    /// separated
+    #[arg(long)]
+    pub shuffle: Option<Shuffle>,
+
+    /// Play the same track again, or the album round again, once a track ends
+    #[arg(long)]
+    pub repeat: Option<Repeat>,
+
+    /// Mark albums skipped as a whole as listened or disliked, so they are not queued again
+    #[arg(long)]
+    pub skip_album_mark: Option<AlbumMark>,
+
+    /// Equalizer preset to start with
+    #[arg(long)]
+    pub eq: Option<Preset>,
+
+    /// Preamp gain in dB applied before the equalizer
+    #[arg(long, allow_negative_numbers = true)]
+    pub preamp: Option<f32>,
+
+    /// Downmix the output to mono
+    #[arg(long, overrides_with = "no_mono")]
+    pub mono: bool,
+
+    /// Keep the output in stereo
+    #[arg(long, overrides_with = "mono")]
+    pub no_mono: bool,
+
+    /// Enable the output limiter, as it is unless the config turns it off
+    #[arg(long, overrides_with = "no_limiter")]
+    pub limiter: bool,
+
+    /// Disable the output limiter
+    #[arg(long, overrides_with = "limiter")]
+    pub no_limiter: bool,
+
+    /// Seconds before a request to Bandcamp is abandoned, 30 by default
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
+    /// Times a failed request to Bandcamp is retried, 3 by default
+    #[arg(long)]
+    pub retries: Option<u32>,
+
+    /// Requests per second made to Bandcamp, 0 for no limit, 1 by default
+    #[arg(long)]
+    pub rate_limit: Option<f64>,
+
+    /// Proxy for all traffic, such as http://host:3128 or socks5://host:1080
+    #[arg(long)]
+    pub proxy: Option<String>,
+
+    /// PEM file of extra root certificates to trust
+    #[arg(long)]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Local address or network interface to connect from
+    #[arg(long)]
+    pub bind: Option<String>,
+
+    /// Most detailed log messages shown: error, warn, info, debug or trace
+    #[arg(long)]
+    pub log_level: Option<Level>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Play without any terminal interface, controlled through the control socket
+    Daemon,
+    /// Send a command to a running daemon: play, pause, toggle, next, previous, status,
+    /// queue [count], lyrics, like, dislike, unmark, volume [0-100], mode [play mode],
+    /// shuffle [shuffle], repeat [off|one|album], replay,
+    /// skip-album [listened|disliked], previous-album or quit
+    Ctl {
+        #[arg(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective config, after the environment and command line are applied
+    Show,
+    /// Print where the config file is read from
+    Path,
+}
+
+impl Args {
+    /// Apply the flags given on the command line over `config`
+    pub fn override_config(&self, config: &mut Config) {
+        macro_rules! set {
+            ($($flag:ident => $section:ident . $key:ident),* $(,)?) => {
+                $(if let Some(value) = &self.$flag {
+                    config.$section.$key = value.clone().into();
+                })*
+            };
+        }
+        set!(
+            socket => controls.socket,
+            mpd => controls.mpd,
+            http => controls.http,
+            stream => controls.stream,
+            device => playback.device,
+            audio_cache => playback.audio_cache,
+            audio_cache_dir => playback.audio_cache_dir,
+            save_dir => playback.save_dir,
+            save_template => playback.save_template,
+            genre => discovery.genre,
+            play_mode => playback.play_mode,
+            shuffle => playback.shuffle,
+            repeat => playback.repeat,
+            skip_album_mark => playback.skip_album_mark,
+            eq => playback.eq,
+            preamp => playback.preamp,
+            timeout => network.timeout,
+            retries => network.retries,
+            rate_limit => network.rate_limit,
+            proxy => network.proxy,
+            ca_bundle => network.ca_bundle,
+            bind => network.bind,
+            log_level => logging.level,
+        );
+        // Paired flags override whichever way they are given, and leave the config alone
+        // when neither is
+        let switch = |config: &mut bool, on: bool, off: bool| {
+            if on || off {
+                *config = on;
+            }
+        };
+        switch(&mut config.controls.tui, self.tui, self.no_tui);
+        switch(
+            &mut config.controls.stream_only,
+            self.stream_only,
+            self.no_stream_only,
+        );
+        switch(&mut config.playback.mono, self.mono, self.no_mono);
+        switch(&mut config.playback.limiter, self.limiter, self.no_limiter);
+    }
+}
+
+#[test]
+fn test_override_config() {
+    let mut config = Config::default();
+    config.playback.mono = true;
+    config.playback.limiter = false;
+    config.network.retries = 5;
+
+    Args::parse_from(["bandmix", "--no-mono", "--limiter", "--timeout", "10"])
+        .override_config(&mut config);
+    assert!(!config.playback.mono);
+    assert!(config.playback.limiter);
+    assert_eq!(config.network.timeout, 10);
+    // Left as the config has it
+    assert_eq!(config.network.retries, 5);
+    assert!(!config.controls.tui);
+}
@@ -0,0 +1,499 @@
+//! Settings read from `config.toml` in the platform config directory, overridden by
+//! `BANDMIX_<SECTION>_<KEY>` environment variables, which are overridden in turn by the
+//! command line.
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::Level;
+
+use crate::{
+    bandcamp::{
+        api::{DiscoveryType, Format, Genre, RecommendedType},
+        client::{ClientSettings, NetworkSettings},
+    },
+    bandmix::{
+        daemon,
+        discovery::{AlbumMark, DiscoverySettings, PlayMode, Repeat},
+        dsp::{DspSettings, Preset},
+        export::{ExportSettings, DEFAULT_TEMPLATE},
+        shuffle::Shuffle,
+    },
+};
+
+pub const CONFIG_NAME: &str = "config.toml";
+/// Names a config file to use in place of the one in the config directory
+pub const CONFIG_VAR: &str = "BANDMIX_CONFIG";
+const ENV_PREFIX: &str = "BANDMIX_";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("invalid config from {0}: {1}")]
+    Parse(String, toml::de::Error),
+    #[error("failed to write config: {0}")]
+    Write(#[from] toml::ser::Error),
+}
+
+pub type Result<T> = std::result::Result<T, ConfigError>;
+
+/// Settings that parse from text on the command line are written the same way in the file
+mod text {
+    use std::{fmt::Display, str::FromStr};
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<T: Display, S: Serializer>(
+            value: &Option<T>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => serializer.collect_str(value),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+        where
+            T: FromStr,
+            T::Err: Display,
+            D: Deserializer<'de>,
+        {
+            Option::<String>::deserialize(deserializer)?
+                .map(|value| value.parse().map_err(D::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+/// What the discover feed is asked for
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    #[serde(with = "text::option", skip_serializing_if = "Option::is_none")]
+    pub genre: Option<Genre>,
+    /// top, new or rec
+    #[serde(
+        rename = "type",
+        with = "text::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub discovery_type: Option<DiscoveryType>,
+    #[serde(with = "text::option", skip_serializing_if = "Option::is_none")]
+    pub format: Option<Format>,
+    /// most or latest, for the rec type
+    #[serde(with = "text::option", skip_serializing_if = "Option::is_none")]
+    pub recommended: Option<RecommendedType>,
+}
+
+/// How far ahead discovery loads, see [`DiscoverySettings`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BufferingConfig {
+    pub discovered_albums: usize,
+    pub albums: usize,
+    pub slow_ahead: usize,
+    pub max_ahead: usize,
+    pub slow_delay_ms: u64,
+    pub poll_ms: u64,
+}
+
+impl Default for BufferingConfig {
+    fn default() -> Self {
+        let settings = DiscoverySettings::default();
+        BufferingConfig {
+            discovered_albums: settings.discovered_albums,
+            albums: settings.albums,
+            slow_ahead: settings.slow_ahead,
+            max_ahead: settings.max_ahead,
+            slow_delay_ms: settings.slow_delay.as_millis() as u64,
+            poll_ms: settings.poll.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FiltersConfig {
+    pub exclude_tags: Vec<String>,
+    pub exclude_artists: Vec<String>,
+    pub replay_listened: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaybackConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(with = "text")]
+    pub play_mode: PlayMode,
+    #[serde(with = "text")]
+    pub shuffle: Shuffle,
+    #[serde(with = "text")]
+    pub repeat: Repeat,
+    #[serde(with = "text::option", skip_serializing_if = "Option::is_none")]
+    pub skip_album_mark: Option<AlbumMark>,
+    #[serde(with = "text")]
+    pub eq: Preset,
+    pub preamp: f32,
+    pub mono: bool,
+    pub limiter: bool,
+    /// Megabytes, 0 disables the audio cache
+    pub audio_cache: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_cache_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_dir: Option<PathBuf>,
+    pub save_template: String,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        PlaybackConfig {
+            device: None,
+            play_mode: PlayMode::default(),
+            shuffle: Shuffle::default(),
+            repeat: Repeat::Off,
+            skip_album_mark: None,
+            eq: Preset::Flat,
+            preamp: 0.0,
+            mono: false,
+            limiter: true,
+            audio_cache: 0,
+            audio_cache_dir: None,
+            save_dir: None,
+            save_template: DEFAULT_TEMPLATE.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlsConfig {
+    /// Name of the MPRIS service on Linux
+    pub dbus_name: String,
+    pub tui: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mpd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    pub stream_only: bool,
+}
+
+impl Default for ControlsConfig {
+    fn default() -> Self {
+        ControlsConfig {
+            dbus_name: "bandmix".to_string(),
+            tui: false,
+            socket: None,
+            mpd: None,
+            http: None,
+            stream: None,
+            stream_only: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Seconds
+    pub timeout: u64,
+    pub retries: u32,
+    /// Requests per second, 0 for no limit
+    pub rate_limit: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind: Option<String>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        let settings = ClientSettings::default();
+        NetworkConfig {
+            timeout: settings.timeout.as_secs(),
+            retries: settings.retries,
+            rate_limit: settings.rate_limit,
+            proxy: None,
+            ca_bundle: None,
+            bind: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// error, warn, info, debug or trace
+    #[serde(with = "text")]
+    pub level: Level,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { level: Level::INFO }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discovery: DiscoveryConfig,
+    pub buffering: BufferingConfig,
+    pub filters: FiltersConfig,
+    pub playback: PlaybackConfig,
+    pub controls: ControlsConfig,
+    pub network: NetworkConfig,
+    pub logging: LoggingConfig,
+}
+
+impl Config {
+    /// The file given, else the one named by `BANDMIX_CONFIG`, else `config.toml` in the
+    /// platform config directory
+    pub fn path(path: Option<&Path>) -> Option<PathBuf> {
+        path.map(Path::to_path_buf)
+            .or_else(|| std::env::var_os(CONFIG_VAR).map(PathBuf::from))
+            .or_else(|| {
+                ProjectDirs::from("", "", "bandmix").map(|dirs| dirs.config_dir().join(CONFIG_NAME))
+            })
+    }
+
+    /// Read the config file, which only has to exist when named explicitly, then apply
+    /// the environment on top
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let explicit = path.is_some() || std::env::var_os(CONFIG_VAR).is_some();
+        let mut config = match Config::path(path) {
+            Some(path) => match std::fs::read_to_string(&path) {
+                Ok(text) => toml::from_str(&text)
+                    .map_err(|error| ConfigError::Parse(path.display().to_string(), error))?,
+                Err(error) if explicit || error.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(ConfigError::Read(path, error));
+                }
+                Err(_) => Config::default(),
+            },
+            None => Config::default(),
+        };
+        config.apply_env(std::env::vars())?;
+        Ok(config)
+    }
+
+    /// Override settings from `BANDMIX_<SECTION>_<KEY>` variables, such as
+    /// `BANDMIX_PLAYBACK_PLAY_MODE=album`. Values are read as TOML where the setting takes
+    /// them, so numbers, booleans and arrays work, and as plain text otherwise. The
+    /// environment is shared with everything else, so variables that fit no setting are
+    /// skipped with a warning rather than stopping bandmix.
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+        let mut table = toml::Table::try_from(&*self)?;
+        for (name, value) in vars {
+            let Some(setting) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if name == CONFIG_VAR {
+                continue;
+            }
+            let setting = setting.to_lowercase();
+            let Some((section, key)) = setting
+                .split_once('_')
+                .filter(|(section, _)| table.get(*section).is_some_and(toml::Value::is_table))
+            else {
+                eprintln!("bandmix: ignoring {}, which names no config section", name);
+                continue;
+            };
+            let typed = toml::from_str::<toml::Table>(&format!("value = {}", value))
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .filter(|typed| !typed.is_str());
+            let mut rejected = None;
+            for value in typed.into_iter().chain([toml::Value::String(value)]) {
+                let mut candidate = table.clone();
+                if let Some(section) = candidate
+                    .get_mut(section)
+                    .and_then(toml::Value::as_table_mut)
+                {
+                    section.insert(key.to_string(), value);
+                }
+                match Config::deserialize(candidate.clone()) {
+                    Ok(_) => {
+                        table = candidate;
+                        rejected = None;
+                        break;
+                    }
+                    Err(error) => rejected = Some(error),
+                }
+            }
+            if let Some(error) = rejected {
+                eprintln!("bandmix: ignoring {}: {}", name, error.message().trim());
+            }
+        }
+        *self = Config::deserialize(table)
+            .map_err(|error| ConfigError::Parse("the environment".to_string(), error))?;
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn socket(&self) -> PathBuf {
+        self.controls
+            .socket
+            .clone()
+            .unwrap_or_else(daemon::default_socket)
+    }
+
+    pub fn export_settings(&self) -> ExportSettings {
+        ExportSettings {
+            dir: self
+                .playback
+                .save_dir
+                .clone()
+                .unwrap_or_else(ExportSettings::default_dir),
+            template: self.playback.save_template.clone(),
+        }
+    }
+
+    pub fn client_settings(&self) -> ClientSettings {
+        ClientSettings {
+            timeout: Duration::from_secs(self.network.timeout),
+            retries: self.network.retries,
+            rate_limit: self.network.rate_limit,
+            network: NetworkSettings {
+                proxy: self.network.proxy.clone(),
+                ca_bundle: self.network.ca_bundle.clone(),
+                bind: self.network.bind.clone(),
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn dsp_settings(&self) -> DspSettings {
+        DspSettings {
+            preset: self.playback.eq,
+            gains: self.playback.eq.gains(),
+            preamp_db: self.playback.preamp,
+            limiter: self.playback.limiter,
+            mono: self.playback.mono,
+        }
+    }
+
+    pub fn discovery_settings(&self) -> DiscoverySettings {
+        let buffering = &self.buffering;
+        DiscoverySettings {
+            genre: self.discovery.genre,
+            discovery_type: self.discovery.discovery_type,
+            format: self.discovery.format,
+            recommended_type: self.discovery.recommended,
+            discovered_albums: buffering.discovered_albums,
+            albums: buffering.albums,
+            slow_ahead: buffering.slow_ahead,
+            max_ahead: buffering.max_ahead,
+            slow_delay: Duration::from_millis(buffering.slow_delay_ms),
+            poll: Duration::from_millis(buffering.poll_ms),
+            exclude_tags: self.filters.exclude_tags.clone(),
+            exclude_artists: self.filters.exclude_artists.clone(),
+            replay_listened: self.filters.replay_listened,
+        }
+    }
+}
+
+#[test]
+fn test_layers() {
+    let mut config: Config = toml::from_str(
+        r#"
+        [discovery]
+        genre = "hip-hop/rap"
+
+        [playback]
+        play_mode = "sampler:2"
+        limiter = false
+
+        [filters]
+        exclude_tags = ["podcasts"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.discovery.genre, Some(Genre::hip_hop_rap));
+    assert_eq!(config.playback.play_mode, PlayMode::Sampler(2));
+    assert_eq!(config.buffering, BufferingConfig::default());
+
+    let vars = |vars: &[(&str, &str)]| -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    };
+    config
+        .apply_env(vars(&[
+            ("BANDMIX_BUFFERING_MAX_AHEAD", "64"),
+            ("BANDMIX_PLAYBACK_SHUFFLE", "album,spacing:2"),
+            ("BANDMIX_LOGGING_LEVEL", "debug"),
+            ("HOME", "/root"),
+        ]))
+        .unwrap();
+    assert_eq!(config.buffering.max_ahead, 64);
+    assert_eq!(config.playback.shuffle.to_string(), "album,spacing:2");
+    assert_eq!(config.logging.level, Level::DEBUG);
+    assert!(!config.playback.limiter);
+
+    // What `config show` prints reads back the same
+    assert_eq!(
+        toml::from_str::<Config>(&config.to_toml().unwrap()).unwrap(),
+        config
+    );
+
+    // Text that reads as another type still fits a text setting
+    config
+        .apply_env(vars(&[
+            ("BANDMIX_PLAYBACK_DEVICE", "1"),
+            ("BANDMIX_CONTROLS_DBUS_NAME", "true"),
+        ]))
+        .unwrap();
+    assert_eq!(config.playback.device.as_deref(), Some("1"));
+    assert_eq!(config.controls.dbus_name, "true");
+
+    // Anything else is left out, keeping the rest
+    let before = config.clone();
+    config
+        .apply_env(vars(&[
+            ("BANDMIX_PLAYBACK_VOLUME", "11"),
+            ("BANDMIX_SOUND_EQ", "flat"),
+            ("BANDMIX_PLAYBACK_PLAY_MODE", "sideways"),
+            ("BANDMIX_HOME", "/opt/bandmix"),
+        ]))
+        .unwrap();
+    assert_eq!(config, before);
+}
@@ -1,20 +1,238 @@
-use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use bandcamp::{api::Site, models::TrackID};
 use bandmix::{
+    cache::AudioCache,
     controls::get_media_controls,
-    discovery::{self, Entry},
-    stream::Player,
+    daemon,
+    discovery::{self, AlbumMark, Entry},
+    dsp::{DspControl, Preset},
+    events::{self, Event},
+    export::{self, ExportSettings},
+    icecast, mpd, status,
+    stream::{self, Player, StreamError},
+    tui::{self, LogWriter, Tui},
+    web,
 };
-use souvlaki::{MediaControlEvent, MediaMetadata};
-use tracing::Level;
+use clap::Parser;
+use cli::{Args, Command, ConfigCommand};
+use config::Config;
+use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata};
+use tokio::sync::mpsc::{self, UnboundedSender};
 use tracing_subscriber::FmtSubscriber;
 
 mod bandcamp;
 mod bandmix;
+mod cli;
+mod config;
 
-async fn new_track(track: &Entry, player: &Player) {
-    println!("NOW PLAYING: {}", track);
-    player.start(&track.url).await;
+/// Print through the log pane while the TUI is running
+macro_rules! output {
+    ($($arg:tt)*) => {
+        bandmix::tui::print(format!($($arg)*))
+    };
+}
+
+/// How often the output device is checked for having gone away
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// How often the shared player status is refreshed
+const STATUS_INTERVAL: Duration = Duration::from_millis(250);
+/// How many times a failing track is retried before moving on
+const MAX_RETRIES: u32 = 3;
+/// Delay before the first retry, doubled for each one after
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// Tracks failing in a row before playback stops trying, likely because the network is down
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+
+/// Reads commands from stdin: `eq [preset]`, `eq <band> <db>`, `mono`, `limiter`, `devices`,
+/// `device [name]`, `mode [play mode]`, `shuffle [shuffle]`, `repeat [off|one|album]`,
+/// `replay`, `skip-album [listened|disliked]`, `previous-album`, `lyrics` and `save`
+fn console(
+    dsp: DspControl,
+    events: UnboundedSender<Event>,
+    export: ExportSettings,
+    cache: Option<Arc<AudioCache>>,
+) {
+    for line in std::io::stdin().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["eq"] => dsp.set_preset(dsp.settings().preset.next()),
+            ["eq", preset] => match preset.parse::<Preset>() {
+                Ok(preset) => dsp.set_preset(preset),
+                Err(_) => eprintln!("Unknown preset: {}", preset),
+            },
+            ["eq", band, gain] => match (band.parse::<usize>(), gain.parse::<f32>()) {
+                (Ok(band), Ok(gain)) => dsp.set_band(band, gain),
+                _ => eprintln!("Usage: eq <band 0-9> <gain dB>"),
+            },
+            ["mono"] => dsp.update(|s| s.mono = !s.mono),
+            ["limiter"] => dsp.update(|s| s.limiter = !s.limiter),
+            ["devices"] => {
+                for device in stream::output_devices() {
+                    println!("{}", device);
+                }
+                continue;
+            }
+            ["mode"] => {
+                println!("{}", discovery::play_mode());
+                continue;
+            }
+            ["mode", mode] => {
+                match mode.parse() {
+                    Ok(mode) => discovery::set_play_mode(mode),
+                    Err(error) => eprintln!("{}", error),
+                }
+                continue;
+            }
+            ["shuffle"] => {
+                println!("{}", discovery::shuffle());
+                continue;
+            }
+            ["shuffle", shuffle] => {
+                match shuffle.parse() {
+                    Ok(shuffle) => discovery::set_shuffle(shuffle),
+                    Err(error) => eprintln!("{}", error),
+                }
+                continue;
+            }
+            ["repeat"] => {
+                discovery::set_repeat(discovery::repeat().next());
+                continue;
+            }
+            ["repeat", repeat] => {
+                match repeat.parse() {
+                    Ok(repeat) => discovery::set_repeat(repeat),
+                    Err(_) => eprintln!("Unknown repeat: {}", repeat),
+                }
+                continue;
+            }
+            ["replay"] | ["skip-album"] | ["skip-album", _] | ["previous-album"] => {
+                let event = match words.as_slice() {
+                    ["replay"] => discovery::album_start()
+                        .map(|position| Event::Jump {
+                            position,
+                            mark_skipped: false,
+                        })
+                        .ok_or("Nothing to replay".to_string()),
+                    ["skip-album", mark] if mark.parse::<AlbumMark>().is_err() => {
+                        Err(format!("Unknown mark: {}", mark))
+                    }
+                    ["skip-album", ..] => discovery::skip_album()
+                        .map(|position| Event::SkipAlbum {
+                            position,
+                            mark: words.get(1).and_then(|mark| mark.parse().ok()),
+                        })
+                        .map_err(|e| e.to_string()),
+                    _ => discovery::previous_album()
+                        .map(|position| Event::Jump {
+                            position,
+                            mark_skipped: false,
+                        })
+                        .map_err(|e| e.to_string()),
+                };
+                match event {
+                    Ok(event) => {
+                        if events.send(event).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => eprintln!("{}", error),
+                }
+                continue;
+            }
+            ["lyrics"] => {
+                let track = status::get().track;
+                match track.lyrics {
+                    Some(lyrics) => println!("{}", lyrics),
+                    None => eprintln!("No lyrics for {}", track.name),
+                }
+                continue;
+            }
+            ["save"] => {
+                let Some(track) = discovery::current() else {
+                    eprintln!("Nothing to save");
+                    continue;
+                };
+                let export = export.clone();
+                let cache = cache.clone();
+                let _ = std::thread::Builder::new()
+                    .name("Save track".to_string())
+                    .spawn(
+                        move || match export::save(&track, &export, cache.as_deref()) {
+                            Ok(path) => println!("SAVED: {}", path.display()),
+                            Err(error) => eprintln!("Failed to save {}: {}", track.name, error),
+                        },
+                    );
+                continue;
+            }
+            ["device", name @ ..] => {
+                let name = (!name.is_empty()).then(|| name.join(" "));
+                if events.send(Event::SetDevice(name)).is_err() {
+                    break;
+                }
+                continue;
+            }
+            _ => continue,
+        }
+        println!("{}", dsp.settings());
+    }
+}
+
+async fn new_track(
+    track: &Entry,
+    player: &Player,
+    controls: &mut MediaControls,
+    events: &UnboundedSender<Event>,
+) {
+    output!("NOW PLAYING: {}", track);
+    status::update(|s| s.track = track.clone());
+    match player.start(track).await {
+        Ok(()) => status::update(|s| s.error = None),
+        Err(error) => {
+            let _ = events.send(Event::TrackError {
+                id: track.id,
+                error,
+            });
+        }
+    }
+    // TODO: handle error
+    let _ = controls.set_metadata(MediaMetadata {
+        title: Some(&track.name),
+        artist: Some(&track.artist),
+        album: Some(&track.album_name),
+        cover_url: track
+            .art_url
+            .as_ref()
+            .or(track.album_art_url.as_ref())
+            .map(String::as_str),
+        duration: player.duration(),
+    });
+}
+
+/// Play `track`, or when discovery has yet to load the track under the cursor, stop what
+/// is playing and send [`Event::Loaded`] once it has, rather than holding up the main loop.
+async fn play_or_wait(
+    track: Option<Entry>,
+    player: &Player,
+    controls: &mut MediaControls,
+    events: &UnboundedSender<Event>,
+) {
+    let position = discovery::position();
+    if track.is_some() || discovery::loaded(position) {
+        return new_track(&track.unwrap_or_default(), player, controls, events).await;
+    }
+    output!("Waiting for more tracks to load");
+    // Also keeps the finished callback of what was playing from moving the cursor on
+    status::update(|s| s.track = Entry::default());
+    player.silence();
+    let events = events.clone();
+    tokio::spawn(async move {
+        discovery::wait_for(position).await;
+        let _ = events.send(Event::Loaded(position));
+    });
 }
 
 #[cfg(target_os = "windows")]
@@ -46,63 +264,373 @@ async fn main() {
     #[cfg(target_os = "windows")]
     let _ = load_icon();
 
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("Setting default subscriber failed");
-
-    let mut controls = get_media_controls();
-    let player = Player::new().expect("Failed to get Player");
-    let update_trigger: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
-    let update_event: Arc<Mutex<MediaControlEvent>> = Arc::new(Mutex::new(MediaControlEvent::Play));
-    let update_trigger_clone: Arc<AtomicBool> = Arc::clone(&update_trigger);
-    let update_event_clone: Arc<Mutex<MediaControlEvent>> = Arc::clone(&update_event);
-    let mut initial = false;
+    let args = Args::parse();
+    if args.list_devices {
+        for device in stream::output_devices() {
+            println!("{}", device);
+        }
+        return;
+    }
+    if let Some(Command::Config {
+        command: ConfigCommand::Path,
+    }) = &args.command
+    {
+        match Config::path(args.config.as_deref()) {
+            Some(path) => println!("{}", path.display()),
+            None => eprintln!("bandmix: no config directory found"),
+        }
+        return;
+    }
+    let config = match Config::load(args.config.as_deref()) {
+        Ok(mut config) => {
+            args.override_config(&mut config);
+            config
+        }
+        Err(error) => {
+            eprintln!("bandmix: {}", error);
+            std::process::exit(1);
+        }
+    };
+    if let Some(Command::Config {
+        command: ConfigCommand::Show,
+    }) = &args.command
+    {
+        match config.to_toml() {
+            Ok(text) => print!("{}", text),
+            Err(error) => {
+                eprintln!("bandmix: {}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(Command::Ctl { command }) = &args.command {
+        let socket = config.socket();
+        if let Err(error) = daemon::ctl(&socket, &command.join(" ")) {
+            eprintln!("bandmix ctl ({}): {}", socket.display(), error);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let headless = matches!(args.command, Some(Command::Daemon));
+
+    let subscriber = FmtSubscriber::builder().with_max_level(config.logging.level);
+    if config.controls.tui && !headless {
+        let subscriber = subscriber.with_writer(LogWriter).with_ansi(false).finish();
+        tracing::subscriber::set_global_default(subscriber)
+    } else {
+        tracing::subscriber::set_global_default(subscriber.finish())
+    }
+    .expect("Setting default subscriber failed");
+
+    if let Err(error) = bandcamp::client::configure(config.client_settings()) {
+        eprintln!("bandmix: {}", error);
+        std::process::exit(1);
+    }
+    let mut controls = get_media_controls(&config.controls.dbus_name);
+    let mut player = Player::new(config.playback.device.as_deref()).expect("Failed to get Player");
+    player.dsp = DspControl::new(config.dsp_settings());
+    if config.playback.audio_cache > 0 {
+        match config
+            .playback
+            .audio_cache_dir
+            .clone()
+            .or_else(AudioCache::default_dir)
+        {
+            Some(dir) => match AudioCache::new(dir, config.playback.audio_cache * 1_000_000) {
+                Ok(cache) => player.cache = Some(Arc::new(cache)),
+                Err(error) => output!("Failed to open audio cache: {}", error),
+            },
+            None => output!("Failed to find a directory for the audio cache"),
+        }
+    }
+    if let Some(address) = &config.controls.stream {
+        match icecast::start(address.as_str()) {
+            Ok(broadcast) => player.broadcast = Some(broadcast),
+            Err(error) => output!("Failed to start stream: {}", error),
+        }
+        player.muted = config.controls.stream_only;
+        player.set_volume(player.volume());
+    } else if config.controls.stream_only {
+        output!("Stream only has no effect without a stream address");
+    }
+    let mut requested_device = config.playback.device.clone();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let send_event = events::control_sender(events_tx.clone());
+    let finished_tx = events_tx.clone();
+    player.on_finished = Some(Arc::new(move |id, result| {
+        let _ = finished_tx.send(match result {
+            Ok(()) => Event::TrackFinished(id),
+            Err(error) => Event::TrackError { id, error },
+        });
+    }));
     player.pause();
+    send_event(MediaControlEvent::Play.into());
+
+    if headless {
+        if let Err(error) = daemon::start(&config.socket(), Arc::clone(&send_event)) {
+            output!("Failed to start control socket: {}", error);
+            return;
+        }
+    } else if config.controls.tui {
+        tui::start(Tui {
+            send_event: Arc::clone(&send_event),
+            dsp: player.dsp.clone(),
+            export: config.export_settings(),
+            cache: player.cache.clone(),
+        });
+    } else {
+        let dsp = player.dsp.clone();
+        let export = config.export_settings();
+        let cache = player.cache.clone();
+        let events = events_tx.clone();
+        let _ = std::thread::Builder::new()
+            .name("Console".to_string())
+            .spawn(move || console(dsp, events, export, cache));
+    }
+
+    if let Some(address) = &config.controls.mpd {
+        if let Err(error) = mpd::start(address.as_str(), Arc::clone(&send_event)) {
+            output!("Failed to start MPD server: {}", error);
+        }
+    }
+
+    if let Some(address) = config.controls.http {
+        if let Err(error) = web::start(address, Arc::clone(&send_event)).await {
+            output!("Failed to start HTTP API: {}", error);
+        }
+    }
 
-    discovery::start(None, None, None, None);
+    discovery::set_play_mode(config.playback.play_mode);
+    discovery::set_shuffle(config.playback.shuffle);
+    discovery::set_repeat(config.playback.repeat);
+    discovery::set_skip_album_mark(config.playback.skip_album_mark);
+    discovery::start(Site::default(), config.discovery_settings());
     controls
         .attach(move |event: MediaControlEvent| {
-            println!("Event received: {:?}", event);
-            let mut enum_guard = update_event_clone.lock().unwrap();
-            *enum_guard = event; // TODO: should I queue up commands?
-            update_trigger_clone.store(true, std::sync::atomic::Ordering::Relaxed);
+            output!("Event received: {:?}", event);
+            send_event(event.into());
         })
         .unwrap();
 
-    let mut last_track = Entry::default();
+    let mut retries: HashMap<TrackID, u32> = HashMap::new();
+    let mut consecutive_failures = 0;
+    let mut status_interval = tokio::time::interval(STATUS_INTERVAL);
+    let mut device_interval = tokio::time::interval(DEVICE_CHECK_INTERVAL);
 
     loop {
-        // TODO: separate user and internal controls
-        if initial && player.empty() {
-            if discovery::mark_current_track().is_none() {
-                eprintln!("Failed to mark current track");
-            }
-            let mut event = update_event.lock().unwrap();
-            *event = MediaControlEvent::Next;
-            update_trigger.store(true, std::sync::atomic::Ordering::Relaxed);
-        }
-        if !update_trigger.load(std::sync::atomic::Ordering::Relaxed) {
-            continue;
-        }
-        let event = match update_event.try_lock() {
-            Ok(event) => event.to_owned(),
-            Err(_) => continue,
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = status_interval.tick() => {
+                status::update(|s| {
+                    s.paused = player.is_paused();
+                    s.position = player.position();
+                    s.duration = player.duration();
+                    s.volume = player.volume();
+                    s.device = player.device_name().to_string();
+                });
+                continue;
+            }
+            _ = device_interval.tick() => {
+                if player.device_lost() {
+                    // Falls back onto the default device if the requested one is still gone
+                    if player
+                        .set_device(requested_device.as_deref())
+                        .await
+                        .is_none()
+                    {
+                        output!("Failed to recover output device");
+                    }
+                    output!("OUTPUT DEVICE: {}", player.device_name());
+                }
+                continue;
+            }
+        };
+        let event = match event {
+            Event::Control(event) => event,
+            // Sent by a track that was since skipped
+            Event::TrackFinished(id) | Event::TrackError { id, .. }
+                if status::get().track.id != id =>
+            {
+                continue;
+            }
+            Event::TrackFinished(_) => {
+                output!("[FINISHED]");
+                consecutive_failures = 0;
+                retries.clear();
+                if let Err(error) = discovery::mark_current_track() {
+                    output!("Failed to mark current track: {}", error);
+                }
+                play_or_wait(
+                    discovery::next_after_finished(),
+                    &player,
+                    &mut controls,
+                    &events_tx,
+                )
+                .await;
+                continue;
+            }
+            Event::UserSkip => {
+                output!("[NEXT]");
+                if let Err(error) = discovery::skip_current_track() {
+                    output!("Failed to mark skipped track: {}", error);
+                }
+                play_or_wait(discovery::next(), &player, &mut controls, &events_tx).await;
+                continue;
+            }
+            Event::UserPrevious => {
+                output!("[PREVIOUS]");
+                play_or_wait(discovery::previous(), &player, &mut controls, &events_tx).await;
+                continue;
+            }
+            Event::TrackError { error, .. } => {
+                let track = status::get().track;
+                output!("[ERROR] {}: {}", track.name, error);
+                status::update(|s| s.error = Some(error.to_string()));
+                let attempts = retries.entry(track.id).or_default();
+                *attempts += 1;
+                if !matches!(error, StreamError::NoStreamUrl) && *attempts <= MAX_RETRIES {
+                    let delay = RETRY_BACKOFF * 2u32.pow(*attempts - 1);
+                    output!("Retrying in {}s", delay.as_secs());
+                    let position = match error {
+                        StreamError::Truncated { played, .. } => played,
+                        _ => Duration::ZERO,
+                    };
+                    let events = events_tx.clone();
+                    let expired = error.is_expired();
+                    tokio::spawn(async move {
+                        if expired {
+                            output!("Stream link expired, reloading {}", track.album_name);
+                            let id = track.id;
+                            match tokio::task::spawn_blocking(move || {
+                                discovery::reload_album_of(id)
+                            })
+                            .await
+                            {
+                                Ok(Err(error)) => output!("Failed to reload album: {}", error),
+                                Err(error) => output!("Failed to reload album: {}", error),
+                                Ok(Ok(())) => {}
+                            }
+                        }
+                        tokio::time::sleep(delay).await;
+                        let _ = events.send(Event::Retry {
+                            id: track.id,
+                            position,
+                        });
+                    });
+                    continue;
+                }
+
+                retries.remove(&track.id);
+                // Only blame the track when it is at fault, a network outage is not its doing
+                if error.is_track_fault() {
+                    if let Err(error) = discovery::fail_current_track() {
+                        output!("Failed to mark failed track: {}", error);
+                    }
+                }
+                consecutive_failures += 1;
+                if consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                    output!(
+                        "{} tracks failed in a row, press play to try again",
+                        consecutive_failures
+                    );
+                    consecutive_failures = 0;
+                    player.pause();
+                    continue;
+                }
+                play_or_wait(discovery::next(), &player, &mut controls, &events_tx).await;
+                continue;
+            }
+            Event::Loaded(position) => {
+                // Moved on since, or already playing
+                if position != discovery::position() {
+                    continue;
+                }
+                let track = discovery::current();
+                if track.as_ref().map(|t| t.id) != Some(status::get().track.id) {
+                    play_or_wait(track, &player, &mut controls, &events_tx).await;
+                }
+                continue;
+            }
+            Event::Retry { id, position } => {
+                let track = status::get().track;
+                // Moved on since
+                if track.id != id {
+                    continue;
+                }
+                // With any stream url reloaded since
+                let track = discovery::current()
+                    .filter(|current| current.id == id)
+                    .unwrap_or(track);
+                output!("[RETRY] {}", track.name);
+                new_track(&track, &player, &mut controls, &events_tx).await;
+                player.seek(position);
+                continue;
+            }
+            Event::Jump {
+                position,
+                mark_skipped,
+            } => {
+                output!("[JUMP] {}", position);
+                let left = discovery::position();
+                let Some(track) = discovery::jump(position) else {
+                    output!("No track at {}", position);
+                    continue;
+                };
+                if mark_skipped {
+                    if let Err(error) = discovery::skip_track_at(left) {
+                        output!("Failed to mark skipped track: {}", error);
+                    }
+                }
+                new_track(&track, &player, &mut controls, &events_tx).await;
+                continue;
+            }
+            Event::SkipAlbum { position, mark } => {
+                output!("[SKIP ALBUM]");
+                let left = discovery::position();
+                let Some(track) = discovery::jump(position) else {
+                    output!("No track at {}", position);
+                    continue;
+                };
+                if let Err(error) = discovery::skip_track_at(left) {
+                    output!("Failed to mark skipped track: {}", error);
+                }
+                if let Some(mark) = mark.or_else(discovery::skip_album_mark) {
+                    if let Err(error) = discovery::mark_album_at(left, mark) {
+                        output!("Failed to mark skipped album: {}", error);
+                    }
+                }
+                new_track(&track, &player, &mut controls, &events_tx).await;
+                continue;
+            }
+            Event::SetDevice(device) => {
+                requested_device = device;
+                if player
+                    .set_device(requested_device.as_deref())
+                    .await
+                    .is_none()
+                {
+                    output!("Failed to switch output device");
+                }
+                output!("OUTPUT DEVICE: {}", player.device_name());
+                continue;
+            }
         };
-        let mut track = Entry::default();
-        initial = true;
         match event {
             MediaControlEvent::Play => {
-                println!("[PLAY]");
-                track = discovery::current().unwrap_or_default();
+                output!("[PLAY]");
 
                 #[cfg(target_os = "windows")]
                 if !player.is_paused() {
-                    println!("[PAUSING ON ASSUMPTION]");
+                    output!("[PAUSING ON ASSUMPTION]");
                     player.pause();
                 } else {
                     if player.empty() {
-                        new_track(&track, &player).await;
+                        play_or_wait(discovery::current(), &player, &mut controls, &events_tx)
+                            .await;
                     }
                     player.play();
                 }
@@ -110,66 +638,45 @@ async fn main() {
                 #[cfg(not(target_os = "windows"))]
                 {
                     if player.empty() {
-                        new_track(&track, &player).await;
+                        play_or_wait(discovery::current(), &player, &mut controls, &events_tx)
+                            .await;
                     }
                     player.play();
                 }
             }
             MediaControlEvent::Pause => {
-                println!("[PAUSE]");
+                output!("[PAUSE]");
                 player.pause();
             }
             MediaControlEvent::Toggle => {
-                println!("[TOGGLE]");
+                output!("[TOGGLE]");
                 if player.is_paused() {
                     player.play();
                 } else {
                     player.pause();
                 };
             }
-            MediaControlEvent::Next => {
-                println!("[NEXT]");
-                if discovery::mark_current_track().is_none() {
-                    eprintln!("Failed to mark last track");
-                }
-                track = discovery::next().unwrap_or_default();
-                new_track(&track, &player).await;
-            }
-            MediaControlEvent::Previous => {
-                println!("[PREVIOUS]");
-                track = discovery::previous().unwrap_or_default();
-                new_track(&track, &player).await;
+            MediaControlEvent::SetVolume(volume) => {
+                output!("[VOLUME] {:.0}%", volume * 100.0);
+                player.set_volume(volume as f32);
             }
             MediaControlEvent::Stop => {
-                println!("[STOP]");
+                output!("[STOP]");
                 player.stop();
                 discovery::stop();
                 break;
             }
             MediaControlEvent::Quit => {
-                println!("[QUIT]");
+                output!("[QUIT]");
                 player.stop();
                 discovery::stop();
                 break;
             }
             _ => {
-                println!("[OTHER]");
+                output!("[OTHER]");
             } // TODO: other media controls
         };
-
-        // TODO: use album id instead
-        if (last_track != track) || (last_track.album_name != track.album_name) {
-            // TODO: duration
-            // TODO: handle error
-            let _ = controls.set_metadata(MediaMetadata {
-                title: Some(&track.name),
-                artist: Some(&track.artist),
-                album: Some(&track.album_name),
-                cover_url: track.album_art_url.as_deref(),
-                ..Default::default()
-            });
-            last_track = track;
-        }
-        update_trigger.store(false, std::sync::atomic::Ordering::Relaxed);
     }
+    tui::stop();
+    daemon::stop();
 }